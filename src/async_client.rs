@@ -0,0 +1,208 @@
+//! Non-blocking counterpart to [`SteamClient`](crate::steam_api::SteamClient), for use inside an async runtime.
+//! Only covers the friend list and summaries for now.
+
+use std::time::Duration;
+use futures::{stream, StreamExt, TryStreamExt};
+use itertools::Itertools;
+use reqwest::{Client, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use crate::sql::DbConnection;
+use crate::steam_api::{
+    checked_base_url, private_profile, Friend, PlayerSummary, SteamFailure, SteamId, DEFAULT_BASE_URL,
+    MAX_ERROR_BODY_BYTES, MAX_IDS_PER_REQUEST,
+};
+use crate::sync::{SyncError, SyncOutcome};
+
+/// How many summary requests are in flight at once by default.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+
+pub struct AsyncSteamClient<'a> {
+    api_key: &'a str,
+    client: Client,
+    concurrency: usize,
+    base_url: Url,
+}
+
+impl<'a> AsyncSteamClient<'a> {
+    pub fn new(api_key: &'a str) -> Self {
+        let client = Client::builder()
+            .user_agent("steam-web-api-consumer/0.1 (cjblake97@gmail.com)")
+            .build()
+            .expect("User-Agent on client was invalid");
+
+        Self {
+            api_key,
+            client,
+            concurrency: DEFAULT_CONCURRENCY,
+            base_url: Url::parse(DEFAULT_BASE_URL).expect("Given an invalid const URL"),
+        }
+    }
+
+    /// Same as [`SteamClient::with_base_url`](crate::steam_api::SteamClient::with_base_url).
+    pub fn with_base_url(mut self, base_url: &str) -> Result<Self, SteamFailure> {
+        let url = Url::parse(base_url).map_err(|_| SteamFailure::InvalidBaseUrl(base_url.to_string()))?;
+        self.base_url = checked_base_url(url)?;
+        Ok(self)
+    }
+
+    /// Most summary requests [`Self::get_player_summaries`] has in flight at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    fn api_url(&self, endpoint: &str, params: &[(&str, &str)]) -> Url {
+        let mut url = self.base_url.join(endpoint).expect("Given an invalid endpoint");
+        url.query_pairs_mut().extend_pairs(params);
+        url
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: Url) -> Result<T, SteamFailure> {
+        let res = self.client.get(url).send().await?;
+        let status = res.status();
+        let body = res.bytes().await?;
+        if !status.is_success() {
+            let body = &body[..body.len().min(MAX_ERROR_BODY_BYTES)];
+            return Err(SteamFailure::Http { status, body: String::from_utf8_lossy(body).into_owned() });
+        }
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub async fn get_friend_list(&self, steam_id: &str) -> Result<Vec<Friend>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct FriendsList {
+            #[serde(default)]
+            friends: Vec<Friend>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            #[serde(rename = "friendslist")]
+            friends_list: FriendsList,
+        }
+
+        let url = self.api_url("ISteamUser/GetFriendList/v0001", &[("key", self.api_key), ("steamid", steam_id)]);
+
+        match self.get_json::<Response>(url).await {
+            Ok(res) => Ok(res.friends_list.friends),
+            Err(err @ SteamFailure::Http { status, .. }) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+                Err(private_profile(steam_id, err))
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetches summaries 100 IDs per request, with up to [`Self::concurrency`] requests at once.
+    /// Results come back in the same chunk order as the blocking client's, whichever request finishes first.
+    pub async fn get_player_summaries(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        let mut chunks = stream::iter(steam_ids.chunks(MAX_IDS_PER_REQUEST).enumerate())
+            .map(|(i, chunk)| async move { Ok::<_, SteamFailure>((i, self.get_player_summaries_chunk(chunk).await?)) })
+            .buffer_unordered(self.concurrency)
+            .try_collect::<Vec<_>>()
+            .await?;
+        chunks.sort_unstable_by_key(|(i, _)| *i);
+
+        Ok(chunks.into_iter().flat_map(|(_, players)| players).collect())
+    }
+
+    async fn get_player_summaries_chunk(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Players {
+            players: Vec<serde_json::Value>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Players,
+        }
+
+        let steam_ids = steam_ids.iter().join(",");
+        let url = self.api_url("ISteamUser/GetPlayerSummaries/v0002", &[("key", self.api_key), ("steamids", steam_ids.as_str())]);
+        let res: Response = self.get_json(url).await?;
+
+        res.response.players.into_iter()
+            .map(|player| -> Result<PlayerSummary, SteamFailure> {
+                let mut summary = PlayerSummary::deserialize(&player)?;
+                summary.raw_json = Some(player.to_string());
+                Ok(summary)
+            })
+            .collect()
+    }
+
+    /// Syncs `steam_id`'s friend list into `db` like [`sync_with_deadline`](crate::sync::sync_with_deadline), but
+    /// the whole sync runs under `tokio::time::timeout`, so a request that hangs is cut off at `deadline` too.
+    /// Each chunk of 100 summaries is saved as soon as it arrives, so a timeout keeps everything saved up to then.
+    pub async fn sync_with_deadline(&self, db: &mut DbConnection, steam_id: &str, deadline: Duration) -> Result<SyncOutcome, SyncError> {
+        let mut saved = 0;
+        let mut total = 0;
+        let res = tokio::time::timeout(deadline, self.sync_chunks(db, steam_id, &mut saved, &mut total)).await;
+        match res {
+            Ok(res) => res,
+            Err(_) => Ok(SyncOutcome::TimedOut { saved, total }),
+        }
+    }
+
+    /// The body of [`Self::sync_with_deadline`]. Keeps `saved` and `total` up to date as it goes, since they're all
+    /// that's left to go by if it's cancelled.
+    async fn sync_chunks(&self, db: &mut DbConnection, steam_id: &str, saved: &mut usize, total: &mut usize) -> Result<SyncOutcome, SyncError> {
+        let mut friends = self.get_friend_list(steam_id).await?;
+        friends.sort_unstable_by_key(|f| f.steam_id);
+        let friend_ids = friends.iter().map(|f| f.steam_id).collect::<Vec<_>>();
+        *total = friends.len();
+
+        db.start_sync()?;
+        for friends_chunk in friends.chunks_mut(MAX_IDS_PER_REQUEST) {
+            let chunk_ids = friends_chunk.iter().map(|f| f.steam_id).collect::<Vec<_>>();
+            let mut summaries = self.get_player_summaries(&chunk_ids).await?;
+            db.upsert_player_summaries(friends_chunk, &mut summaries)?;
+            *saved += summaries.len();
+        }
+
+        db.mark_removed(&friend_ids)?;
+        db.record_friend_count()?;
+        Ok(SyncOutcome::Completed { friends: *total })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use httpmock::prelude::*;
+    use crate::transport::mock;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sync_with_deadline_cuts_off_a_hung_request() {
+        let ids = (1..=250).collect::<Vec<_>>();
+        let chunk = |ids: &[i64]| ids.iter().join(",");
+        let server = MockServer::start_async().await;
+        server.mock_async(|when, then| {
+            when.method(GET).path("/ISteamUser/GetFriendList/v0001");
+            then.status(200).body(mock::friend_list_body(&ids));
+        }).await;
+        for fast in [&ids[..100], &ids[100..200]] {
+            server.mock_async(|when, then| {
+                when.method(GET).path("/ISteamUser/GetPlayerSummaries/v0002").query_param("steamids", chunk(fast));
+                then.status(200).body(mock::summaries_body(&chunk(fast)));
+            }).await;
+        }
+        // Far longer than the deadline, and the client has no timeout of its own to save it
+        server.mock_async(|when, then| {
+            when.method(GET).path("/ISteamUser/GetPlayerSummaries/v0002").query_param("steamids", chunk(&ids[200..]));
+            then.status(200).delay(Duration::from_secs(30)).body(mock::summaries_body(&chunk(&ids[200..])));
+        }).await;
+        let client = AsyncSteamClient::new("key").with_base_url(&server.base_url()).unwrap();
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        let started = Instant::now();
+        let outcome = client.sync_with_deadline(&mut db, "1", Duration::from_secs(2)).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(10), "Took {:?}", started.elapsed());
+        assert_eq!(SyncOutcome::TimedOut { saved: 200, total: 250 }, outcome);
+        assert_eq!(200, db.get_current_friends().unwrap().len());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::steam_api::{SteamApi, SteamFailure, SteamId};
+
+
+/// Who is friends with whom, as seen by a single crawl. Friendships are undirected, so an edge may show up in
+/// both users' lists (or only one, if the other wasn't crawled).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendGraph {
+    pub friends: BTreeMap<SteamId, Vec<SteamId>>,
+}
+
+
+/// Crawls friend lists out from `root`, `depth` lists deep: 1 is just `root`'s, 2 adds each of their friends',
+/// and so on. Nobody's list is fetched twice, and the crawl stops early once `max_lists` have been requested.
+/// Private friend lists are left out, but those users still show up in everyone else's lists.
+pub fn crawl(api: &dyn SteamApi, root: &SteamId, depth: u8, max_lists: usize) -> Result<FriendGraph, SteamFailure> {
+    let mut graph = FriendGraph::default();
+    let mut seen = HashSet::from([*root]);
+    let mut frontier = vec![*root];
+    let mut requested = 0;
+    'crawl: for _ in 0..depth {
+        let mut next = Vec::new();
+        for steam_id in frontier {
+            if requested == max_lists {
+                break 'crawl;
+            }
+            requested += 1;
+
+            let friends = match api.get_friend_list(&steam_id.to_string()) {
+                Ok(friends) => friends.into_iter().map(|f| f.steam_id).collect::<Vec<_>>(),
+                Err(SteamFailure::PrivateProfile(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            for &friend in &friends {
+                if seen.insert(friend) {
+                    next.push(friend);
+                }
+            }
+            graph.friends.insert(steam_id, friends);
+        }
+        frontier = next;
+    }
+
+    Ok(graph)
+}
+
+
+/// What changed between two [`FriendGraph`]s, see [`FriendGraph::diff`]. Everything is sorted, and edges are
+/// always `(lower, higher)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphDiff {
+    pub nodes_added: Vec<SteamId>,
+    pub nodes_removed: Vec<SteamId>,
+    pub edges_added: Vec<(SteamId, SteamId)>,
+    pub edges_removed: Vec<(SteamId, SteamId)>,
+}
+
+
+impl FriendGraph {
+    /// Everyone in the graph, whether they were crawled or only showed up in someone's friend list.
+    pub fn nodes(&self) -> BTreeSet<SteamId> {
+        self.friends.iter()
+            .flat_map(|(id, friends)| std::iter::once(id).chain(friends))
+            .copied()
+            .collect()
+    }
+
+    /// Every friendship once, as `(lower, higher)`.
+    pub fn edges(&self) -> BTreeSet<(SteamId, SteamId)> {
+        self.friends.iter()
+            .flat_map(|(&a, friends)| friends.iter().map(move |&b| (a.min(b), a.max(b))))
+            .filter(|(a, b)| a != b)
+            .collect()
+    }
+
+    /// Changes going from `self` to `newer`.
+    pub fn diff(&self, newer: &FriendGraph) -> GraphDiff {
+        let (old_nodes, new_nodes) = (self.nodes(), newer.nodes());
+        let (old_edges, new_edges) = (self.edges(), newer.edges());
+
+        GraphDiff {
+            nodes_added: new_nodes.difference(&old_nodes).copied().collect(),
+            nodes_removed: old_nodes.difference(&new_nodes).copied().collect(),
+            edges_added: new_edges.difference(&old_edges).copied().collect(),
+            edges_removed: old_edges.difference(&new_edges).copied().collect(),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), serde_json::Error> {
+        let file = File::create(path).map_err(serde_json::Error::io)?;
+        serde_json::to_writer(BufWriter::new(file), self)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, serde_json::Error> {
+        let file = File::open(path).map_err(serde_json::Error::io)?;
+        serde_json::from_reader(BufReader::new(file))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(friends: &[(i64, &[i64])]) -> FriendGraph {
+        FriendGraph {
+            friends: friends.iter()
+                .map(|(id, friends)| (SteamId(*id), friends.iter().copied().map(SteamId).collect()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = graph(&[
+            (1, &[2, 3]),
+            (2, &[1]),
+            (3, &[1, 4]),
+        ]);
+        // 4 dropped out, 5 joined, and 2 <-> 3 became friends (listed on both sides)
+        let new = graph(&[
+            (1, &[2, 3]),
+            (2, &[1, 3]),
+            (3, &[1, 2, 5]),
+        ]);
+
+        let diff = old.diff(&new);
+
+        assert_eq!(vec![SteamId(5)], diff.nodes_added);
+        assert_eq!(vec![SteamId(4)], diff.nodes_removed);
+        assert_eq!(vec![(SteamId(2), SteamId(3)), (SteamId(3), SteamId(5))], diff.edges_added);
+        assert_eq!(vec![(SteamId(3), SteamId(4))], diff.edges_removed);
+    }
+
+    #[test]
+    fn test_diff_of_identical_graphs_is_empty() {
+        let g = graph(&[(1, &[2]), (2, &[1])]);
+
+        assert_eq!(GraphDiff::default(), g.diff(&g.clone()));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let g = graph(&[(1, &[2, 3]), (2, &[1])]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("friend_graph.json");
+
+        g.save(&path).unwrap();
+        let loaded = FriendGraph::load(&path).unwrap();
+
+        assert_eq!(g, loaded);
+    }
+}
@@ -0,0 +1,73 @@
+//! Steam community level math. Every level up to 10 costs 100 XP, every level from 11 to 20 costs 200 XP,
+//! and so on in bands of ten.
+
+/// Namespace for the XP curve, so level math can be done locally instead of asking `GetBadges`.
+pub struct SteamLevel;
+
+impl SteamLevel {
+    /// Total XP needed to reach `level` from 0.
+    pub fn xp_for_level(level: u32) -> u64 {
+        let tens = u128::from(level / 10);
+        let rest = u128::from(level % 10);
+        // Each full band of ten costs 10 * 100 * band, and the leftover levels cost 100 * (band + 1) each
+        let xp = 100 * (5 * tens * (tens + 1) + rest * (tens + 1));
+        u64::try_from(xp).unwrap_or(u64::MAX)
+    }
+
+    /// The level `xp` total XP gets you to.
+    pub fn from_xp(xp: u64) -> u32 {
+        // Binary search for the highest level we can afford
+        let (mut low, mut high) = (0u32, u32::MAX);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if Self::xp_for_level(mid) <= xp {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+
+    /// XP still missing to go from `xp` to the next level.
+    pub fn xp_to_next_level(xp: u64) -> u64 {
+        Self::xp_for_level(Self::from_xp(xp).saturating_add(1)).saturating_sub(xp)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xp_for_level() {
+        assert_eq!(0, SteamLevel::xp_for_level(0));
+        assert_eq!(100, SteamLevel::xp_for_level(1));
+        assert_eq!(1000, SteamLevel::xp_for_level(10));
+        assert_eq!(1200, SteamLevel::xp_for_level(11));
+        assert_eq!(3000, SteamLevel::xp_for_level(20));
+        assert_eq!(15000, SteamLevel::xp_for_level(50));
+        assert_eq!(55000, SteamLevel::xp_for_level(100));
+    }
+
+    #[test]
+    fn test_from_xp() {
+        assert_eq!(0, SteamLevel::from_xp(0));
+        assert_eq!(0, SteamLevel::from_xp(99));
+        assert_eq!(1, SteamLevel::from_xp(100));
+        assert_eq!(10, SteamLevel::from_xp(1199));
+        assert_eq!(11, SteamLevel::from_xp(1200));
+        assert_eq!(100, SteamLevel::from_xp(55000));
+        assert_eq!(100, SteamLevel::from_xp(56099));
+        // Doesn't overflow or loop forever
+        assert!(SteamLevel::from_xp(u64::MAX) > 0);
+    }
+
+    #[test]
+    fn test_xp_to_next_level() {
+        assert_eq!(100, SteamLevel::xp_to_next_level(0));
+        assert_eq!(150, SteamLevel::xp_to_next_level(1050));
+        assert_eq!(1100, SteamLevel::xp_to_next_level(55000));
+    }
+}
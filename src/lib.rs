@@ -0,0 +1,9 @@
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod graph;
+pub mod level;
+pub mod metrics;
+pub mod steam_api;
+pub mod sql;
+pub mod sync;
+pub mod transport;
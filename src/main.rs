@@ -1,28 +1,274 @@
-mod steam_api;
-mod sql;
-
-use anyhow::Result;
-use steam_api::SteamClient;
-use crate::sql::DbConnection;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+use steam_web_api_consumer::{
+    sql::{DbConnection, SyncReport, DB_NAME},
+    steam_api::{RetryPolicy, SteamClient, SteamId},
+};
 
 const MY_ID: &str = "76561197996714010";
 
-fn main() -> Result<()> {
-    let api_key = std::env::var("STEAM_API_KEY")
-        .unwrap_or_else(|_| {
-            rpassword::prompt_password("Enter your Steam API key: ")
-                .expect("Couldn't read a Steam API key")
-        });
-
-    let client = SteamClient::new(&api_key);
-    let mut friends = client.get_friend_list(MY_ID)?;
-    let mut friend_details = client.get_player_summaries(&friends.iter().map(|f| f.steam_id).collect::<Vec<_>>())?;
-    debug_assert_eq!(friends.len(), friend_details.len());
+#[derive(Debug, Parser)]
+#[command(about = "Tracks a Steam friend list in a local SQLite DB")]
+struct Cli {
+    /// Whose friend list to track. Falls back to the config file's `default_steam_id`
+    #[arg(long, global = true)]
+    steam_id: Option<String>,
+    /// Falls back to the config file's `db_path`, then `steam.db`
+    #[arg(long, global = true, env = "STEAM_DB_PATH")]
+    db_path: Option<PathBuf>,
+    /// Move a corrupted DB aside to `<db_path>.corrupt` and start over with an empty one
+    #[arg(long, global = true)]
+    recover: bool,
+    /// Defaults to `sync`
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// From the config file, used when `STEAM_API_KEY` isn't set
+    #[arg(skip)]
+    api_key: Option<String>,
+}
+
+impl Cli {
+    /// Fills in whatever wasn't given on the command line or in the environment from `config`.
+    fn apply_config(&mut self, config: Config) {
+        self.steam_id = self.steam_id.take().or(config.default_steam_id);
+        self.db_path = self.db_path.take().or(config.db_path);
+        self.api_key = config.steam_api_key;
+    }
+
+    fn steam_id(&self) -> &str {
+        self.steam_id.as_deref().unwrap_or(MY_ID)
+    }
+
+    fn db_path(&self) -> &Path {
+        self.db_path.as_deref().unwrap_or(Path::new(DB_NAME))
+    }
+}
+
+/// `~/.config/steam-web-api-consumer/config.toml`, or under `$XDG_CONFIG_HOME` if that's set. Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    steam_api_key: Option<String>,
+    default_steam_id: Option<String>,
+    db_path: Option<PathBuf>,
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("steam-web-api-consumer").join("config.toml"))
+    }
 
-    let mut db = DbConnection::new_with_default_name()?;
+    /// The config file's contents, or the defaults if there isn't one.
+    fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).with_context(|| format!("Couldn't parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Fetch the friend list and summaries from Steam and save them
+    Sync {
+        /// Print what would change instead of writing it
+        #[arg(long)]
+        dry_run: bool,
+        /// Print what changed as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print current friends from the DB
+    List,
+    /// Print every name someone has gone by
+    History {
+        steam_id: String,
+        /// Only names seen since then, e.g. `2024-01-01T00:00:00Z`
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+    },
+    /// Look up the Steam ID behind a vanity URL
+    Resolve { vanity: String },
+    /// Print DB diagnostics for bug reports
+    Doctor,
+}
+
+/// Catches an empty or mangled key up front, since Steam only answers those with an unhelpful 403.
+/// `source` is where the key came from, for the error message.
+fn check_api_key(api_key: &str, source: &str) -> Result<()> {
+    if api_key.is_empty() {
+        bail!("The API key from {source} is empty");
+    }
+    if api_key.len() != 32 || !api_key.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("The API key from {source} is malformed, it should be 32 hex characters");
+    }
+    Ok(())
+}
+
+/// From `STEAM_API_KEY`, then the config file, then a prompt.
+fn api_key(cli: &Cli) -> Result<String> {
+    let (api_key, source) = if let Ok(api_key) = std::env::var("STEAM_API_KEY") {
+        (api_key, "STEAM_API_KEY")
+    } else if let Some(api_key) = &cli.api_key {
+        (api_key.clone(), "the config file")
+    } else {
+        let api_key = rpassword::prompt_password("Enter your Steam API key: ")
+            .expect("Couldn't read a Steam API key");
+        (api_key, "the prompt")
+    };
+    check_api_key(&api_key, source)?;
+    Ok(api_key)
+}
+
+fn open_db(cli: &Cli) -> Result<DbConnection> {
+    let mut db = if cli.recover {
+        DbConnection::open_or_recover(cli.db_path())?
+    } else {
+        DbConnection::new(cli.db_path())?
+    };
     db.create_tables()?;
-    db.update_player_summaries(&mut friends, &mut friend_details)?;
-    drop(db);
+    db.set_owner(SteamId::try_from(cli.steam_id())?)?;
+    Ok(db)
+}
+
+fn sync(cli: &Cli, dry_run: bool, json: bool) -> Result<()> {
+    let api_key = api_key(cli)?;
+    let started_at = Utc::now();
+    let timer = Instant::now();
+    let client = SteamClient::builder(&api_key)
+        .retry_policy(RetryPolicy::new(5, Duration::from_secs(1)))
+        .build()?;
+    let mut friends = client.get_friend_list(cli.steam_id())?;
+    let mut friend_details = client.get_player_summaries(&friends.iter().map(|f| f.steam_id).collect::<Vec<_>>())?;
+    // Steam leaves out summaries for profiles it can't show us (e.g. deleted accounts), so this is expected now and then
+    if friend_details.len() < friends.len() {
+        tracing::warn!(
+            "{} of {} friends had no player summary",
+            friends.len() - friend_details.len(),
+            friends.len(),
+        );
+    }
+
+    let mut db = open_db(cli)?;
+    if dry_run {
+        println!("{:#?}", db.preview_player_summaries(&friends, &friend_details)?);
+        return Ok(());
+    }
+    let report = match db.update_player_summaries_if_changed(&mut friends, &mut friend_details)? {
+        Some(report) => report,
+        None => SyncReport { total_current: db.count_current_friends()?, ..SyncReport::default() },
+    };
+    db.record_friend_count()?;
+    db.record_presence(&friend_details)?;
+    db.record_sync(started_at, timer.elapsed())?;
+    if json {
+        println!("{}", serde_json::to_string(&report)?);
+    }
 
     Ok(())
 }
+
+fn main() -> Result<()> {
+    let mut cli = Cli::parse();
+    cli.apply_config(Config::load()?);
+    // Warnings only unless `RUST_LOG` asks for more, e.g. `RUST_LOG=steam_web_api_consumer=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")))
+        .with_writer(std::io::stderr)
+        .init();
+
+    match &cli.command {
+        None => sync(&cli, false, false)?,
+        Some(Command::Sync { dry_run, json }) => sync(&cli, *dry_run, *json)?,
+        Some(Command::List) => {
+            for friend in open_db(&cli)?.get_current_friends()? {
+                println!("{}\t{}\t{}", friend.steam_id, friend.persona_name, friend.profile_url);
+            }
+        },
+        Some(Command::History { steam_id, since }) => {
+            let db = open_db(&cli)?;
+            let steam_id = SteamId::try_from(steam_id.as_str())?;
+            let history = match since {
+                Some(since) => db.get_name_history_since(steam_id, *since)?,
+                None => db.get_name_history(steam_id)?,
+            };
+            for (name, updated_at) in history {
+                println!("{}\t{name}", updated_at.format("%F %T"));
+            }
+        },
+        Some(Command::Resolve { vanity }) => {
+            let api_key = api_key(&cli)?;
+            println!("{}", SteamClient::new(&api_key).resolve(vanity)?);
+        },
+        // Not `open_db`, so diagnosing a DB doesn't change it
+        Some(Command::Doctor) => print!("{}", DbConnection::new(cli.db_path())?.diagnostics()?),
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use super::*;
+
+    #[test]
+    fn test_check_api_key() {
+        assert!(check_api_key("0123456789ABCDEF0123456789abcdef", "STEAM_API_KEY").is_ok());
+        assert_eq!("The API key from the config file is empty", check_api_key("", "the config file").unwrap_err().to_string());
+        assert!(check_api_key("0123456789ABCDEF", "STEAM_API_KEY").unwrap_err().to_string().contains("malformed"));
+        assert!(check_api_key("0123456789ABCDEF0123456789abcdeg", "the prompt").unwrap_err().to_string().contains("malformed"));
+    }
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["steam", "--recover", "history", "76561197960287930"]);
+        assert!(cli.recover);
+        assert_eq!(MY_ID, cli.steam_id());
+        assert!(matches!(cli.command, Some(Command::History { steam_id, since: None }) if steam_id == "76561197960287930"));
+
+        let cli = Cli::parse_from(["steam", "history", "76561197960287930", "--since", "2024-01-01T00:00:00Z"]);
+        assert!(matches!(cli.command, Some(Command::History { since: Some(since), .. }) if since.timestamp() == 1704067200));
+
+        let cli = Cli::parse_from(["steam", "sync", "--dry-run", "--steam-id", "1"]);
+        assert_eq!("1", cli.steam_id());
+        assert!(matches!(cli.command, Some(Command::Sync { dry_run: true, json: false })));
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let config: Config = toml::from_str(r#"
+            steam_api_key = "0123456789ABCDEF0123456789abcdef"
+            default_steam_id = "2"
+            db_path = "/tmp/steam.db"
+        "#).unwrap();
+
+        // The command line wins over the config file
+        let mut cli = Cli::parse_from(["steam", "--steam-id", "1", "list"]);
+        cli.apply_config(config);
+        assert_eq!("1", cli.steam_id());
+        assert_eq!(Path::new("/tmp/steam.db"), cli.db_path());
+        assert_eq!(Some("0123456789ABCDEF0123456789abcdef"), cli.api_key.as_deref());
+
+        let mut cli = Cli::parse_from(["steam", "list"]);
+        cli.apply_config(Config::default());
+        assert_eq!(MY_ID, cli.steam_id());
+
+        assert!(toml::from_str::<Config>("steam_apikey = \"typo\"").is_err());
+    }
+}
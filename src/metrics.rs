@@ -0,0 +1,30 @@
+//! Prometheus text exposition helpers, see https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::fmt::Display;
+
+/// Appends a single sample, with its `HELP` and `TYPE` lines, to `out`.
+pub(crate) fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    out.push_str(&format!("# HELP {name} {help}\n"));
+    out.push_str(&format!("# TYPE {name} {kind}\n"));
+    out.push_str(&format!("{name} {value}\n"));
+}
+
+/// Serves whatever `render` returns at `/metrics` on `addr` (e.g. `0.0.0.0:9184`). Blocks forever.
+#[cfg(feature = "metrics")]
+pub fn serve(addr: &str, render: impl Fn() -> String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use tiny_http::{Header, Response, Server};
+
+    let server = Server::http(addr)?;
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+        .expect("Content-Type header was invalid");
+
+    for request in server.incoming_requests() {
+        if request.url() == "/metrics" {
+            request.respond(Response::from_string(render()).with_header(content_type.clone()))?;
+        } else {
+            request.respond(Response::empty(404))?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,29 +1,472 @@
-use rusqlite::Connection;
-use crate::steam_api::{Friend, PlayerSummary};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use rusqlite::{
+    types::FromSql,
+    Connection,
+    ErrorCode,
+    OptionalExtension,
+    Row,
+    ToSql,
+};
+use serde::Serialize;
+use crate::metrics;
+use crate::steam_api::{App, AppId, Friend, OwnedGame, PersonaState, PlayerSummary, Playtime, SteamId};
 
-const DB_NAME: &str = "steam.db";
+pub const DB_NAME: &str = "steam.db";
+
+/// How long to wait on another process's lock (e.g. someone querying during a sync) before giving up.
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// [`DbConnection::mark_removed`] on `conn`, which may be partway through a transaction.
+fn mark_removed(conn: &Connection, owner: SteamId, current: &[SteamId]) -> Result<usize, rusqlite::Error> {
+    // An empty `IN ()` is only valid in SQLite, so don't lean on it when everyone's gone
+    let not_current = if current.is_empty() {
+        String::new()
+    } else {
+        format!("AND steam_id NOT IN ({})", placeholders(current.len()))
+    };
+    let update = format!(
+        "UPDATE
+            player_summaries
+        SET
+            updated_at = CURRENT_TIMESTAMP,
+            removed_at = CURRENT_TIMESTAMP,
+            removed_with_name = persona_name
+        WHERE
+            owner_steam_id = ?
+            AND removed_at IS NULL
+            {}
+    ",
+        not_current
+    );
+    conn.execute(&update, rusqlite::params_from_iter(std::iter::once(&owner).chain(current)))
+}
+
+/// [`DbConnection::start_sync`] on `conn`, which may be partway through a transaction.
+fn start_sync(conn: &Connection, owner: SteamId) -> Result<(), rusqlite::Error> {
+    let key = format!("previous_sync_at:{owner}");
+    let previous: Option<String> = conn.query_row(
+        "SELECT MAX(updated_at) FROM player_summaries WHERE owner_steam_id = ?",
+        [owner],
+        |row| row.get(0)
+    )?;
+    match previous {
+        Some(previous) => set_sync_metadata(conn, &key, previous),
+        // Nothing synced yet, so there's nothing to compare against
+        None => conn.execute("DELETE FROM sync_metadata WHERE key = ?", [key]).map(|_| ()),
+    }
+}
+
+fn set_sync_metadata(conn: &Connection, key: &str, value: impl ToSql) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO sync_metadata (key, value) VALUES (?1, ?2)
+        ON CONFLICT (key) DO UPDATE SET value = ?2",
+        (key, value)
+    )?;
+    Ok(())
+}
+
+/// [`DbConnection::upsert_player_summaries`] on `conn`, which should be a transaction.
+fn upsert_player_summaries(
+    conn: &Connection,
+    owner: SteamId,
+    store_raw_json: bool,
+    friends: &mut [Friend],
+    summaries: &mut [PlayerSummary],
+) -> Result<(), rusqlite::Error> {
+    friends.sort_unstable_by_key(|f| f.steam_id);
+    summaries.sort_unstable_by_key(|s| s.steam_id);
+    // Has to run before the upsert below clears `removed_at`
+    let mut readd_stmt = conn.prepare(
+        "INSERT INTO readd_history
+            (owner_steam_id, steam_id, removed_at)
+        SELECT
+            owner_steam_id, steam_id, removed_at
+        FROM
+            player_summaries
+        WHERE
+            owner_steam_id = ?
+            AND steam_id = ?
+            AND removed_at IS NOT NULL
+        "
+    )?;
+    let mut summary_stmt = conn.prepare(
+        "INSERT INTO player_summaries
+            (steam_id, persona_name, profile_url, friend_since, raw_json, relationship, owner_steam_id)
+        VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (owner_steam_id, steam_id) DO
+            UPDATE SET
+                persona_name = ?2, profile_url = ?3, raw_json = ?5, relationship = ?6,
+                friend_since = COALESCE(friend_since, ?4), updated_at = CURRENT_TIMESTAMP, removed_at = NULL
+        "
+    )?;
+    let mut nickname_stmt = conn.prepare(
+        "INSERT INTO name_history
+            (steam_id, persona_name)
+        VALUES
+            (?, ?)
+        ON CONFLICT (steam_id, persona_name) DO UPDATE SET updated_at = CURRENT_TIMESTAMP
+        "
+    )?;
+
+    // Steam leaves out summaries for some profiles (e.g. deleted accounts), so match them up by ID rather than
+    // position. Friends without a summary are skipped since we'd have nothing to store for them.
+    let summaries = summaries.iter().map(|s| (s.steam_id, s)).collect::<HashMap<_, _>>();
+    // The same friend can show up twice, in which case the earliest `friend_since` wins, and any over a pending invite
+    let mut deduped_friends: BTreeMap<SteamId, &Friend> = BTreeMap::new();
+    for friend in friends.iter() {
+        deduped_friends.entry(friend.steam_id)
+            .and_modify(|earliest| {
+                if friend.friend_since.is_some() && (earliest.friend_since.is_none() || friend.friend_since < earliest.friend_since) {
+                    *earliest = friend;
+                }
+            })
+            .or_insert(friend);
+    }
+    for (friend, summary) in deduped_friends.values().filter_map(|f| Some((f, *summaries.get(&f.steam_id)?))) {
+        readd_stmt.execute((owner, &summary.steam_id))?;
+        summary_stmt.execute((
+            &summary.steam_id,
+            &summary.persona_name,
+            &summary.profile_url,
+            &friend.friend_since,
+            summary.raw_json.as_ref().filter(|_| store_raw_json),
+            &friend.relationship,
+            owner,
+        ))?;
+        nickname_stmt.execute((&summary.steam_id, &summary.persona_name))?;
+    }
+
+    Ok(())
+}
+
+/// `?,?,...,?` with `count` placeholders, for `IN (...)` clauses.
+fn placeholders(count: usize) -> String {
+    let mut s = "?,".repeat(count);
+    // Get rid of the trailing comma
+    s.pop();
+    s
+}
+
+/// Changes to tables that already existed, oldest first. A DB's `PRAGMA user_version` is how many of these it's had.
+/// Only ever add to the end, each one runs against whatever schema the ones before it left behind.
+const MIGRATIONS: &[fn(&Connection) -> Result<(), rusqlite::Error>] = &[
+    migrate_player_summaries_columns,
+    migrate_owners,
+    migrate_nullable_friend_since,
+];
+
+/// 1: Columns were added to `player_summaries` before the schema was versioned, so older DBs can be missing any of
+/// `first_seen_at`, `removed_with_name`, `raw_json`, and `relationship`. `ALTER TABLE` can't add `first_seen_at` with
+/// its default, so this rebuilds the table and copies over whichever columns are there.
+fn migrate_player_summaries_columns(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "CREATE TABLE player_summaries_v1 (
+            steam_id INT8 PRIMARY KEY NOT NULL,
+            persona_name TEXT NOT NULL,
+            profile_url TEXT NOT NULL,
+            friend_since TIMESTAMP NOT NULL,
+            first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            removed_at TIMESTAMP,
+            removed_with_name TEXT,
+            raw_json TEXT,
+            relationship TEXT NOT NULL DEFAULT 'friend'
+        )",
+        ()
+    )?;
+    let columns = conn.prepare("SELECT name FROM pragma_table_info('player_summaries')")?
+        .query_map((), |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?
+        .join(", ");
+    conn.execute(&format!("INSERT INTO player_summaries_v1 ({columns}) SELECT {columns} FROM player_summaries"), ())?;
+    conn.execute("DROP TABLE player_summaries", ())?;
+    conn.execute("ALTER TABLE player_summaries_v1 RENAME TO player_summaries", ())?;
+
+    Ok(())
+}
+
+/// 2: Friend lists are kept per owner (see [`DbConnection::set_owner`]), so the same person can be on more than one.
+/// Everything from before then gets owner 0, which the first owner to sync takes over.
+fn migrate_owners(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "CREATE TABLE player_summaries_v2 (
+            owner_steam_id INT8 NOT NULL DEFAULT 0,
+            steam_id INT8 NOT NULL,
+            persona_name TEXT NOT NULL,
+            profile_url TEXT NOT NULL,
+            friend_since TIMESTAMP NOT NULL,
+            first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            removed_at TIMESTAMP,
+            removed_with_name TEXT,
+            raw_json TEXT,
+            relationship TEXT NOT NULL DEFAULT 'friend',
+            PRIMARY KEY (owner_steam_id, steam_id)
+        )",
+        ()
+    )?;
+    conn.execute(
+        "INSERT INTO player_summaries_v2
+            (steam_id, persona_name, profile_url, friend_since, first_seen_at, updated_at, removed_at, removed_with_name, raw_json, relationship)
+        SELECT
+            steam_id, persona_name, profile_url, friend_since, first_seen_at, updated_at, removed_at, removed_with_name, raw_json, relationship
+        FROM player_summaries",
+        ()
+    )?;
+    conn.execute("DROP TABLE player_summaries", ())?;
+    conn.execute("ALTER TABLE player_summaries_v2 RENAME TO player_summaries", ())?;
+
+    // Only there to be added to if the table already exists, otherwise `create_tables` makes it with the column
+    let has_readd_history: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'readd_history')",
+        (),
+        |row| row.get(0)
+    )?;
+    if has_readd_history {
+        conn.execute("ALTER TABLE readd_history ADD COLUMN owner_steam_id INT8 NOT NULL DEFAULT 0", ())?;
+    }
+
+    Ok(())
+}
+
+/// 3: Pending invites don't have a `friend_since` yet, so the column can be NULL. SQLite can't drop a `NOT NULL`
+/// constraint in place, so this rebuilds the table the same way [`migrate_owners`] did.
+fn migrate_nullable_friend_since(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "CREATE TABLE player_summaries_v3 (
+            owner_steam_id INT8 NOT NULL DEFAULT 0,
+            steam_id INT8 NOT NULL,
+            persona_name TEXT NOT NULL,
+            profile_url TEXT NOT NULL,
+            friend_since TIMESTAMP,
+            first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            removed_at TIMESTAMP,
+            removed_with_name TEXT,
+            raw_json TEXT,
+            relationship TEXT NOT NULL DEFAULT 'friend',
+            PRIMARY KEY (owner_steam_id, steam_id)
+        )",
+        ()
+    )?;
+    conn.execute(
+        "INSERT INTO player_summaries_v3
+            (owner_steam_id, steam_id, persona_name, profile_url, friend_since, first_seen_at, updated_at, removed_at, removed_with_name, raw_json, relationship)
+        SELECT
+            owner_steam_id, steam_id, persona_name, profile_url, friend_since, first_seen_at, updated_at, removed_at, removed_with_name, raw_json, relationship
+        FROM player_summaries",
+        ()
+    )?;
+    conn.execute("DROP TABLE player_summaries", ())?;
+    conn.execute("ALTER TABLE player_summaries_v3 RENAME TO player_summaries", ())?;
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DbError {
+    #[error("Database {} is corrupted. Restore it from a backup, or rerun with --recover to move it aside and start over", path.display())]
+    Corrupted { path: PathBuf },
+    #[error("Database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Error moving corrupted database aside: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Couldn't switch the database to WAL mode, it's still in {0:?} mode")]
+    JournalMode(String),
+    #[error("Couldn't read the database's size on disk: {0}")]
+    Size(std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Database error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("Error writing export: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Error writing JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Error writing CSV: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// A friend as stored in `player_summaries`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StoredFriend {
+    pub steam_id: SteamId,
+    pub persona_name: String,
+    pub profile_url: String,
+    /// `None` for pending invites.
+    pub friend_since: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl StoredFriend {
+    /// Expects the columns in field order, i.e. `SELECT steam_id, persona_name, profile_url, friend_since, updated_at`.
+    fn from_row(row: &Row<'_>) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            steam_id: row.get(0)?,
+            persona_name: row.get(1)?,
+            profile_url: row.get(2)?,
+            friend_since: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    }
+}
+
+/// Details about the DB to include in bug reports, see [`DbConnection::diagnostics`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub sqlite_version: String,
+    /// `PRAGMA user_version`
+    pub schema_version: i64,
+    /// Size on disk of the DB file and its `-wal` file together. `None` for an in-memory DB.
+    pub size_bytes: Option<u64>,
+    /// Every table with its row count, sorted by name.
+    pub tables: Vec<(String, usize)>,
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "SQLite version: {}", self.sqlite_version)?;
+        writeln!(f, "Schema version: {}", self.schema_version)?;
+        match self.size_bytes {
+            Some(size_bytes) => writeln!(f, "Size: {size_bytes} bytes")?,
+            None => writeln!(f, "Size: in memory")?,
+        }
+        writeln!(f, "Tables:")?;
+        for (table, rows) in &self.tables {
+            writeln!(f, "    {table}: {rows} rows")?;
+        }
+        Ok(())
+    }
+}
+
+/// Hash of everything a sync would write, for [`DbConnection::update_player_summaries_if_changed`].
+/// `DefaultHasher` isn't guaranteed stable across Rust releases, which only costs one redundant write after an upgrade.
+fn fingerprint(friends: &mut [Friend], summaries: &mut [PlayerSummary]) -> String {
+    friends.sort_unstable_by_key(|f| f.steam_id);
+    summaries.sort_unstable_by_key(|s| s.steam_id);
+
+    let mut hasher = DefaultHasher::new();
+    for friend in friends.iter() {
+        (friend.steam_id, friend.relationship, friend.friend_since).hash(&mut hasher);
+    }
+    for summary in summaries.iter() {
+        (summary.steam_id, &summary.persona_name, &summary.profile_url, &summary.raw_json).hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+
+/// What [`DbConnection::update_player_summaries`] would change, see [`DbConnection::preview_player_summaries`].
+/// Every list is sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdatePlan {
+    /// Active friends who'd be marked removed.
+    pub removed: Vec<SteamId>,
+    /// New friends, and removed ones who'd come back.
+    pub added: Vec<SteamId>,
+    /// Active friends whose name would change.
+    pub renamed: Vec<SteamId>,
+}
+
+
+/// What a call to [`DbConnection::update_player_summaries`] changed, in the same terms as [`UpdatePlan`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    pub added: usize,
+    pub removed: usize,
+    pub renamed: usize,
+    /// Active friends once the update is done.
+    pub total_current: usize,
+}
+
+
+/// How much the friend list moved over a trailing window, see [`DbConnection::churn_rate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChurnStats {
+    pub added: usize,
+    pub removed: usize,
+    pub net_change: i64,
+    /// `(added + removed) / currently active friends`, as a percentage. 0 when nobody is active.
+    pub churn_pct: f64,
+}
 
 pub struct DbConnection {
     conn: Connection,
+    store_raw_json: bool,
+    owner: SteamId,
 }
 
 impl DbConnection {
+    /// Opens (or creates) the DB at `path`. Fails with [`DbError::Corrupted`] if it isn't a usable SQLite DB.
+    /// File-backed DBs are switched to WAL mode so they can be read from other processes mid-sync.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)?;
+
+        // Opening is lazy, so make SQLite actually read the file to find out if it's usable
+        match conn.query_row("SELECT COUNT(*) FROM sqlite_master", (), |row| row.get::<_, i64>(0)) {
+            Ok(_) => {},
+            Err(e) if matches!(e.sqlite_error_code(), Some(ErrorCode::NotADatabase | ErrorCode::DatabaseCorrupt)) => {
+                return Err(DbError::Corrupted { path: path.to_path_buf() });
+            },
+            Err(e) => return Err(e.into()),
+        }
+
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // SQLite answers with the mode it ended up in rather than failing. In-memory DBs can't use WAL and stay "memory".
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode = WAL", (), |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") && !journal_mode.eq_ignore_ascii_case("memory") {
+            return Err(DbError::JournalMode(journal_mode));
+        }
+
+        Ok(Self { conn, store_raw_json: false, owner: SteamId(0) })
+    }
+
     /// Creates a Sqlite DB with the name `steam.db` in the current directory.
-    pub fn new_with_default_name() -> Result<Self, rusqlite::Error> {
-        Ok(Self {
-            conn: Connection::open(DB_NAME)?,
-        })
+    pub fn new_with_default_name() -> Result<Self, DbError> {
+        Self::new(DB_NAME)
+    }
+
+    /// Same as [`Self::new`], except a corrupted DB is renamed to `<path>.corrupt` and a fresh one is created
+    /// in its place. Everything in the old DB is left behind, so only use this for unattended runs that
+    /// would rather start over than stop.
+    pub fn open_or_recover<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        let path = path.as_ref();
+        match Self::new(path) {
+            Err(DbError::Corrupted { .. }) => {
+                let mut corrupt_path = path.as_os_str().to_owned();
+                corrupt_path.push(".corrupt");
+                std::fs::rename(path, &corrupt_path)?;
+                Self::new(path)
+            },
+            res => res,
+        }
     }
 
     pub fn create_tables(&self) -> Result<(), rusqlite::Error> {
+        self.migrate()?;
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS player_summaries (
-            steam_id INT8 PRIMARY KEY NOT NULL,
+            owner_steam_id INT8 NOT NULL DEFAULT 0,
+            steam_id INT8 NOT NULL,
             persona_name TEXT NOT NULL,
             profile_url TEXT NOT NULL,
-            friend_since TIMESTAMP NOT NULL,
+            friend_since TIMESTAMP,
+            first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
             updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
-            removed_at TIMESTAMP
+            removed_at TIMESTAMP,
+            removed_with_name TEXT,
+            raw_json TEXT,
+            relationship TEXT NOT NULL DEFAULT 'friend',
+            PRIMARY KEY (owner_steam_id, steam_id)
         )",
             ()
         )?;
@@ -38,100 +481,829 @@ impl DbConnection {
             ()
         )?;
 
+        // One row each time someone who was marked removed shows up in the friend list again
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS readd_history (
+                steam_id INT8 NOT NULL,
+                removed_at TIMESTAMP NOT NULL,
+                readded_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                owner_steam_id INT8 NOT NULL DEFAULT 0
+            )",
+            ()
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS owned_games (
+                steam_id INT8 NOT NULL,
+                appid INT4 NOT NULL,
+                playtime_forever INT4 NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                PRIMARY KEY (steam_id, appid)
+            )",
+            ()
+        )?;
+
+        // A snapshot of every game's playtime each time `update_owned_games` runs, for deltas over time
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS playtime_history (
+                steam_id INT8 NOT NULL,
+                appid INT4 NOT NULL,
+                playtime_forever INT4 NOT NULL,
+                recorded_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                PRIMARY KEY (steam_id, appid, recorded_at)
+            )",
+            ()
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS playtime_history_game ON playtime_history (steam_id, appid)",
+            ()
+        )?;
+
+        // People followed on purpose rather than because they're on the friend list
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS tracked_users (
+                steam_id INT8 PRIMARY KEY NOT NULL,
+                persona_name TEXT NOT NULL,
+                profile_url TEXT NOT NULL,
+                first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+            )",
+            ()
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS friend_count_history (
+                root INT8 NOT NULL,
+                count INT4 NOT NULL,
+                recorded_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+            )",
+            ()
+        )?;
+
+        // Local copy of `GetAppList`, so games can be stored by ID alone and still be looked up by name
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS app_names (
+                appid INT4 PRIMARY KEY NOT NULL,
+                name TEXT NOT NULL
+            )",
+            ()
+        )?;
+
+        // Only changes are recorded, see `record_presence`
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS presence_history (
+                steam_id INT8 NOT NULL,
+                persona_state INT2 NOT NULL,
+                recorded_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+            )",
+            ()
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS presence_history_steam_id ON presence_history (steam_id)",
+            ()
+        )?;
+
+        // `value` is deliberately untyped so each key reads back as whatever type it was written as
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS sync_metadata (
+                key TEXT PRIMARY KEY NOT NULL,
+                value NOT NULL
+            )",
+            ()
+        )?;
+
+        Ok(())
+    }
+
+    /// Brings a DB made by an older version of this program up to the current schema, by running whichever of
+    /// [`MIGRATIONS`] it hasn't had yet. Returns the schema version it ends up at. [`Self::create_tables`] calls this
+    /// first, so there's usually no need to call it directly.
+    pub fn migrate(&self) -> Result<usize, rusqlite::Error> {
+        let version: usize = self.conn.query_row("PRAGMA user_version", (), |row| row.get(0))?;
+        let latest = MIGRATIONS.len();
+
+        // Nothing to bring up to date, `create_tables` makes everything with the latest schema
+        let is_new: bool = self.conn.query_row(
+            "SELECT NOT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'player_summaries')",
+            (),
+            |row| row.get(0)
+        )?;
+        if is_new {
+            self.conn.pragma_update(None, "user_version", latest)?;
+            return Ok(latest);
+        }
+        // Made by a newer version of this program, which we can't do anything about
+        if version >= latest {
+            return Ok(version);
+        }
+
+        let txn = self.conn.unchecked_transaction()?;
+        for migration in &MIGRATIONS[version..] {
+            migration(&txn)?;
+        }
+        txn.pragma_update(None, "user_version", latest)?;
+        txn.commit()?;
+
+        Ok(latest)
+    }
+
+    /// Also keep each summary exactly as Steam sent it in `player_summaries.raw_json`, so fields we don't have columns
+    /// for can still be queried with SQLite's JSON functions, see [`Self::query_raw_field`]. Off by default.
+    pub fn set_store_raw_json(&mut self, store_raw_json: bool) {
+        self.store_raw_json = store_raw_json;
+    }
+
+    /// Whose friend list this connection reads and writes, for keeping several accounts' friends in one DB.
+    /// Everything about `player_summaries` (and `readd_history`) is scoped to `owner`, so syncing one account never
+    /// marks another's friends removed. Unset, it's owner 0, which is also where rows from before owners existed
+    /// live: the first owner set here takes those over, so set the account those were synced for first.
+    pub fn set_owner(&mut self, owner: SteamId) -> Result<(), rusqlite::Error> {
+        if owner != SteamId(0) {
+            self.conn.execute("UPDATE player_summaries SET owner_steam_id = ? WHERE owner_steam_id = 0", [owner])?;
+            self.conn.execute("UPDATE readd_history SET owner_steam_id = ? WHERE owner_steam_id = 0", [owner])?;
+        }
+        self.owner = owner;
         Ok(())
     }
 
+    #[cfg(test)]
+    pub(crate) fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    fn get_sync_metadata<T: FromSql>(&self, key: &str) -> Result<Option<T>, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT value FROM sync_metadata WHERE key = ?",
+            [key],
+            |row| row.get(0)
+        ).optional()
+    }
+
+    fn set_sync_metadata(&self, key: &str, value: impl ToSql) -> Result<(), rusqlite::Error> {
+        set_sync_metadata(&self.conn, key, value)
+    }
+
+    /// Records when the latest sync started and how long it took, for [`Self::metrics_text`].
+    pub fn record_sync(&self, started_at: DateTime<Utc>, duration: std::time::Duration) -> Result<(), rusqlite::Error> {
+        self.set_sync_metadata("last_sync_at", started_at)?;
+        self.set_sync_metadata("last_sync_duration_secs", duration.as_secs_f64())
+    }
+
+    /// Adds the owner's current number of active friends to their friend count history, and returns it.
+    /// See [`Self::set_owner`].
+    pub fn record_friend_count(&self) -> Result<u32, rusqlite::Error> {
+        self.conn.query_row(
+            "INSERT INTO friend_count_history
+                (root, count)
+            SELECT ?1, COUNT(*) FROM player_summaries WHERE owner_steam_id = ?1 AND removed_at IS NULL
+            RETURNING count",
+            [self.owner],
+            |row| row.get(0)
+        )
+    }
+
+    /// Every friend count recorded for `root` by [`Self::record_friend_count`], oldest first.
+    pub fn friend_count_series(&self, root: SteamId) -> Result<Vec<(DateTime<Utc>, u32)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT recorded_at, count FROM friend_count_history WHERE root = ? ORDER BY recorded_at, rowid"
+        )?;
+        let series = stmt.query_map([root], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(series)
+    }
+
+    /// SQLite version, schema version, size, and row counts, for bug reports.
+    pub fn diagnostics(&self) -> Result<Diagnostics, DbError> {
+        let table_names = self.conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name"
+        )?.query_map((), |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>, _>>()?;
+
+        let mut tables = Vec::with_capacity(table_names.len());
+        for table in table_names {
+            let rows = self.conn.query_row(
+                &format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\"")),
+                (),
+                |row| row.get(0)
+            )?;
+            tables.push((table, rows));
+        }
+
+        // In-memory DBs have an empty path
+        let size_bytes = match self.conn.path().filter(|path| !path.is_empty()) {
+            Some(path) => {
+                // Anything written since the last checkpoint is only in the WAL
+                let wal_bytes = std::fs::metadata(format!("{path}-wal")).map_or(0, |wal| wal.len());
+                Some(std::fs::metadata(path).map_err(DbError::Size)?.len() + wal_bytes)
+            },
+            None => None,
+        };
+
+        Ok(Diagnostics {
+            sqlite_version: rusqlite::version().to_string(),
+            schema_version: self.conn.query_row("PRAGMA user_version", (), |row| row.get(0))?,
+            size_bytes,
+            tables,
+        })
+    }
+
+    /// Friend counts and the latest sync's timing in the Prometheus text format.
+    /// The sync metrics are left out until [`Self::record_sync`] has been called once.
+    pub fn metrics_text(&self) -> Result<String, rusqlite::Error> {
+        let (total, active): (usize, usize) = self.conn.query_row(
+            "SELECT COUNT(*), COUNT(*) FILTER (WHERE removed_at IS NULL) FROM player_summaries WHERE owner_steam_id = ?",
+            [self.owner],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
+
+        let mut out = String::new();
+        metrics::write_metric(&mut out, "steam_friends_total", "gauge", "Friends ever recorded, including removed ones", total);
+        metrics::write_metric(&mut out, "steam_friends_active", "gauge", "Friends not marked as removed", active);
+        if let Some(last_sync) = self.get_sync_metadata::<DateTime<Utc>>("last_sync_at")? {
+            metrics::write_metric(&mut out, "steam_last_sync_timestamp_seconds", "gauge", "Unix time the latest sync started", last_sync.timestamp());
+        }
+        if let Some(duration) = self.get_sync_metadata::<f64>("last_sync_duration_secs")? {
+            metrics::write_metric(&mut out, "steam_last_sync_duration_seconds", "gauge", "How long the latest sync took", duration);
+        }
+
+        Ok(out)
+    }
+
+    /// Same as [`Self::update_player_summaries`], but skips all the writes if `friends` and `summaries` are identical
+    /// to the last call's, which saves a lot of churn for frequent syncs. Returns `None` if it skipped, in which case
+    /// the only write is the sync time (`last_sync_at`, see [`Self::record_sync`]) so it's still clear a sync ran.
+    pub fn update_player_summaries_if_changed(&mut self, friends: &mut [Friend], summaries: &mut [PlayerSummary]) -> Result<Option<SyncReport>, rusqlite::Error> {
+        let fingerprint = fingerprint(friends, summaries);
+        let key = format!("content_fingerprint:{}", self.owner);
+        if self.get_sync_metadata::<String>(&key)?.as_ref() == Some(&fingerprint) {
+            self.set_sync_metadata("last_sync_at", Utc::now())?;
+            return Ok(None);
+        }
+
+        let report = self.update_player_summaries(friends, summaries)?;
+        self.set_sync_metadata(&key, fingerprint)?;
+        Ok(Some(report))
+    }
+
     /// Does the following steps, in order:
     ///     1) Updates `removed_at` for anyone not in `summaries`
     ///     2) Upserts the new players in `summaries`, updating `updated_at` to whenever this program is run.
+    ///        Anyone previously marked removed is made active again, and the re-add is logged in `readd_history`.
     /// NOTE: This function will sort `friends` and `summaries`.
-    pub fn update_player_summaries(&mut self, friends: &mut [Friend], summaries: &mut [PlayerSummary]) -> Result<(), rusqlite::Error> {
+    #[tracing::instrument(skip_all, fields(owner = %self.owner))]
+    pub fn update_player_summaries(&mut self, friends: &mut [Friend], summaries: &mut [PlayerSummary]) -> Result<SyncReport, rusqlite::Error> {
+        let plan = self.preview_player_summaries(friends, summaries)?;
         let curr_player_ids = summaries.iter().map(|s| s.steam_id).collect::<Vec<_>>();
+        // One transaction for both, so a failed upsert can't leave everyone marked removed
+        let txn = self.conn.transaction()?;
+        start_sync(&txn, self.owner)?;
+        mark_removed(&txn, self.owner, &curr_player_ids)?;
+        upsert_player_summaries(&txn, self.owner, self.store_raw_json, friends, summaries)?;
+        txn.commit()?;
 
-        let substitution_string = {
-            let mut s = "?,".repeat(summaries.len());
-            // Get rid of the trailing comma
-            s.pop();
-            s
+        let report = SyncReport {
+            added: plan.added.len(),
+            removed: plan.removed.len(),
+            renamed: plan.renamed.len(),
+            total_current: self.count_current_friends()?,
         };
-        let update = format!(
-            "UPDATE
-                player_summaries
-            SET
-                updated_at = CURRENT_TIMESTAMP,
-                removed_at = CURRENT_TIMESTAMP
-            WHERE
-                removed_at IS NULL
-                AND steam_id NOT IN ({})
-        ",
-            substitution_string
+        tracing::info!(
+            added = report.added,
+            removed = report.removed,
+            renamed = report.renamed,
+            total_current = report.total_current,
+            "Updated player summaries"
         );
-        self.conn.execute(&update, rusqlite::params_from_iter(curr_player_ids))?;
+        Ok(report)
+    }
+
+    /// How many friends aren't marked removed.
+    pub fn count_current_friends(&self) -> Result<usize, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM player_summaries WHERE owner_steam_id = ? AND removed_at IS NULL",
+            [self.owner],
+            |row| row.get(0)
+        )
+    }
+
+    /// Works out what [`Self::update_player_summaries`] would do with the same arguments, without writing anything.
+    pub fn preview_player_summaries(&self, friends: &[Friend], summaries: &[PlayerSummary]) -> Result<UpdatePlan, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT steam_id, persona_name FROM player_summaries WHERE owner_steam_id = ? AND removed_at IS NULL"
+        )?;
+        let active = stmt.query_map([self.owner], |row| Ok((row.get::<_, SteamId>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let summary_ids = summaries.iter().map(|s| s.steam_id).collect::<BTreeSet<_>>();
+        let friend_ids = friends.iter().map(|f| f.steam_id).collect::<BTreeSet<_>>();
+        let mut plan = UpdatePlan {
+            removed: active.keys().filter(|id| !summary_ids.contains(id)).copied().collect(),
+            ..UpdatePlan::default()
+        };
+        // Summaries without a matching friend aren't written, same as the real thing
+        for summary in summaries.iter().filter(|s| friend_ids.contains(&s.steam_id)) {
+            match active.get(&summary.steam_id) {
+                None => plan.added.push(summary.steam_id),
+                Some(name) if *name != summary.persona_name => plan.renamed.push(summary.steam_id),
+                Some(_) => {},
+            }
+        }
+
+        plan.removed.sort_unstable();
+        plan.added.sort_unstable();
+        plan.added.dedup();
+        plan.renamed.sort_unstable();
+        plan.renamed.dedup();
+        Ok(plan)
+    }
+
+    /// Marks the start of a sync done a chunk at a time with [`Self::upsert_player_summaries`], so
+    /// [`Self::friends_added_in_last_sync`] can tell what it added. Call it before the first chunk.
+    /// [`Self::update_player_summaries`] does this itself.
+    pub fn start_sync(&self) -> Result<(), rusqlite::Error> {
+        start_sync(&self.conn, self.owner)
+    }
+
+    /// Sets `removed_at` for every active player not in `current`, returning how many that was.
+    /// Their name at that moment is kept in `removed_with_name`, so it survives them coming back under a new one.
+    pub fn mark_removed(&self, current: &[SteamId]) -> Result<usize, rusqlite::Error> {
+        mark_removed(&self.conn, self.owner, current)
+    }
+
+    /// Upserts the players in `summaries` in a single transaction, leaving anyone else untouched.
+    /// Players previously marked removed are made active again and logged in `readd_history`.
+    /// NOTE: This function will sort `friends` and `summaries`.
+    pub fn upsert_player_summaries(&mut self, friends: &mut [Friend], summaries: &mut [PlayerSummary]) -> Result<(), rusqlite::Error> {
+        let txn = self.conn.transaction()?;
+        upsert_player_summaries(&txn, self.owner, self.store_raw_json, friends, summaries)?;
+        txn.commit()
+    }
 
-        friends.sort_unstable_by(|f1, f2| f1.steam_id.cmp(&f2.steam_id));
-        summaries.sort_unstable_by(|s1, s2| s1.steam_id.cmp(&s2.steam_id));
+    /// Replaces `steam_id`'s rows in `owned_games` with `games`, and adds their current playtimes to `playtime_history`.
+    pub fn update_owned_games(&mut self, steam_id: SteamId, games: &[OwnedGame]) -> Result<(), rusqlite::Error> {
         let txn = self.conn.transaction()?;
         {
-            let mut summary_stmt = txn.prepare(
-                "INSERT INTO player_summaries
-                    (steam_id, persona_name, profile_url, friend_since)
+            txn.execute("DELETE FROM owned_games WHERE steam_id = ?", [steam_id])?;
+            let mut owned_stmt = txn.prepare(
+                "INSERT INTO owned_games
+                    (steam_id, appid, playtime_forever)
                 VALUES
-                    (?, ?, ?, ?)
-                ON CONFLICT (steam_id) DO
-                    UPDATE SET persona_name = ?, profile_url = ?, updated_at = CURRENT_TIMESTAMP
+                    (?, ?, ?)
                 "
             )?;
-            let mut nickname_stmt = txn.prepare(
-                "INSERT INTO name_history
-                    (steam_id, persona_name)
+            // Ignoring conflicts only matters for two runs in the same second, where the first snapshot is as good as any
+            let mut history_stmt = txn.prepare(
+                "INSERT OR IGNORE INTO playtime_history
+                    (steam_id, appid, playtime_forever)
                 VALUES
-                    (?, ?)
-                ON CONFLICT (steam_id, persona_name) DO UPDATE SET updated_at = CURRENT_TIMESTAMP
+                    (?, ?, ?)
                 "
             )?;
 
-            for (friend, summary) in std::iter::zip(friends, summaries) {
-                summary_stmt.execute((
-                    &summary.steam_id,
-                    &summary.persona_name,
-                    &summary.profile_url,
-                    &friend.friend_since,
-                    &summary.persona_name,
-                    &summary.profile_url,
-                ))?;
-                nickname_stmt.execute((&summary.steam_id, &summary.persona_name))?;
+            for game in games {
+                owned_stmt.execute((steam_id, game.app_id, game.playtime_forever))?;
+                history_stmt.execute((steam_id, game.app_id, game.playtime_forever))?;
             }
         }
         txn.commit()?;
 
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::thread::sleep;
-    use std::time::Duration;
-    use chrono::{DateTime, Utc};
-    use crate::steam_api::{Friend, Relationship};
-    use crate::steam_api::SteamId;
-    use super::*;
 
-    #[derive(Debug)]
-    struct PlayerSummariesRow {
-        steam_id: SteamId,
-        persona_name: String,
-        profile_url: String,
-        friend_since: DateTime<Utc>,
-        updated_at: DateTime<Utc>,
-        removed_at: Option<DateTime<Utc>>,
+    /// Saves `apps` (i.e. [`crate::steam_api::SteamClient::get_app_list`]) to `app_names`, overwriting any names that
+    /// changed. It's a ~100k row write, so it all goes in one transaction.
+    pub fn cache_app_names(&mut self, apps: &[App]) -> Result<(), rusqlite::Error> {
+        let txn = self.conn.transaction()?;
+        {
+            let mut stmt = txn.prepare(
+                "INSERT INTO app_names
+                    (appid, name)
+                VALUES
+                    (?1, ?2)
+                ON CONFLICT (appid) DO UPDATE SET name = ?2
+                "
+            )?;
+            for app in apps {
+                stmt.execute((app.app_id, &app.name))?;
+            }
+        }
+        txn.commit()
     }
 
-    #[derive(Debug)]
-    struct NameHistoryRow {
-        steam_id: SteamId,
-        persona_name: String,
-        updated_at: DateTime<Utc>,
+    /// `app_id`'s name from the last [`Self::cache_app_names`], if it was there.
+    pub fn app_name(&self, app_id: AppId) -> Result<Option<String>, rusqlite::Error> {
+        self.conn.query_row("SELECT name FROM app_names WHERE appid = ?", [app_id], |row| row.get(0)).optional()
+    }
+
+    /// Adds everyone in `summaries` whose persona state changed since it was last recorded to `presence_history`,
+    /// returning how many that was. Anyone seen for the first time counts as a change.
+    pub fn record_presence(&mut self, summaries: &[PlayerSummary]) -> Result<usize, rusqlite::Error> {
+        let txn = self.conn.transaction()?;
+        let mut recorded = 0;
+        {
+            let mut stmt = txn.prepare(
+                "INSERT INTO presence_history
+                    (steam_id, persona_state)
+                SELECT ?1, ?2
+                WHERE ?2 IS NOT (
+                    SELECT persona_state FROM presence_history WHERE steam_id = ?1 ORDER BY rowid DESC LIMIT 1
+                )"
+            )?;
+            for summary in summaries {
+                recorded += stmt.execute((summary.steam_id, summary.persona_state))?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(recorded)
+    }
+
+    /// Every persona state change recorded for `steam_id` by [`Self::record_presence`], oldest first.
+    pub fn presence_history(&self, steam_id: SteamId) -> Result<Vec<(DateTime<Utc>, PersonaState)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT recorded_at, persona_state FROM presence_history WHERE steam_id = ? ORDER BY rowid"
+        )?;
+        let history = stmt.query_map([steam_id], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(history)
+    }
+
+    /// Saves summaries for users on the watchlist, see [`crate::sync::resolve_and_sync`]. Their names go into
+    /// `name_history` like friends' do.
+    pub fn upsert_tracked_users(&mut self, summaries: &[PlayerSummary]) -> Result<(), rusqlite::Error> {
+        let txn = self.conn.transaction()?;
+        {
+            let mut tracked_stmt = txn.prepare(
+                "INSERT INTO tracked_users
+                    (steam_id, persona_name, profile_url)
+                VALUES
+                    (?1, ?2, ?3)
+                ON CONFLICT (steam_id) DO
+                    UPDATE SET persona_name = ?2, profile_url = ?3, updated_at = CURRENT_TIMESTAMP
+                "
+            )?;
+            let mut nickname_stmt = txn.prepare(
+                "INSERT INTO name_history
+                    (steam_id, persona_name)
+                VALUES
+                    (?, ?)
+                ON CONFLICT (steam_id, persona_name) DO UPDATE SET updated_at = CURRENT_TIMESTAMP
+                "
+            )?;
+
+            for summary in summaries {
+                tracked_stmt.execute((&summary.steam_id, &summary.persona_name, &summary.profile_url))?;
+                nickname_stmt.execute((&summary.steam_id, &summary.persona_name))?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Makes sure every active friend's current name is in `name_history`, adding any that are missing.
+    /// Returns how many entries were added.
+    pub fn repair_history_consistency(&mut self) -> Result<usize, rusqlite::Error> {
+        self.conn.execute(
+            "INSERT INTO name_history
+                (steam_id, persona_name)
+            SELECT DISTINCT
+                p.steam_id, p.persona_name
+            FROM
+                player_summaries p
+            WHERE
+                p.removed_at IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM name_history n WHERE n.steam_id = p.steam_id AND n.persona_name = p.persona_name
+                )
+            ",
+            ()
+        )
+    }
+
+    /// Deletes friends removed more than `older_than` ago, returning how many. Their `name_history` goes too, unless
+    /// someone else still has them as a friend or they're on the watchlist.
+    pub fn prune_removed(&mut self, older_than: Duration) -> Result<usize, rusqlite::Error> {
+        let cutoff = Utc::now() - older_than;
+        let txn = self.conn.transaction()?;
+        let pruned = txn.execute(
+            "DELETE FROM player_summaries
+            WHERE owner_steam_id = ? AND removed_at IS NOT NULL AND julianday(removed_at) < julianday(?)",
+            (self.owner, cutoff)
+        )?;
+        txn.execute(
+            "DELETE FROM name_history
+            WHERE
+                steam_id NOT IN (SELECT steam_id FROM player_summaries)
+                AND steam_id NOT IN (SELECT steam_id FROM tracked_users)
+            ",
+            ()
+        )?;
+        txn.commit()?;
+
+        Ok(pruned)
+    }
+
+    /// Every friend not marked as removed, ordered by `steam_id`.
+    pub fn get_current_friends(&self) -> Result<Vec<StoredFriend>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT steam_id, persona_name, profile_url, friend_since, updated_at
+            FROM player_summaries
+            WHERE owner_steam_id = ? AND removed_at IS NULL
+            ORDER BY steam_id"
+        )?;
+        let friends = stmt.query_map([self.owner], StoredFriend::from_row)?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(friends)
+    }
+
+    /// Friends whose `friend_since` is in `[start, end)`, including anyone since removed, sorted by `steam_id`.
+    /// Unlike `updated_at`, that's when the friendship actually started rather than when we last synced them.
+    pub fn friends_added_between(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<StoredFriend>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT steam_id, persona_name, profile_url, friend_since, updated_at
+            FROM player_summaries
+            WHERE owner_steam_id = ?1 AND julianday(friend_since) >= julianday(?2) AND julianday(friend_since) < julianday(?3)
+            ORDER BY steam_id"
+        )?;
+        let friends = stmt.query_map((self.owner, start, end), StoredFriend::from_row)?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(friends)
+    }
+
+    /// Current friends who showed up (for the first time, or again after being removed) in the latest sync that
+    /// changed anything, as opposed to ones who were already there and just got refreshed. That's anyone who appeared
+    /// after the previous sync's latest `updated_at`, see [`Self::start_sync`]. Everyone current if there was no
+    /// previous sync. Sorted by `steam_id`.
+    pub fn friends_added_in_last_sync(&self) -> Result<Vec<StoredFriend>, rusqlite::Error> {
+        let previous_sync_at = self.get_sync_metadata::<String>(&format!("previous_sync_at:{}", self.owner))?;
+        let mut stmt = self.conn.prepare(
+            "SELECT p.steam_id, p.persona_name, p.profile_url, p.friend_since, p.updated_at
+            FROM player_summaries p
+            WHERE p.owner_steam_id = ?1 AND p.removed_at IS NULL AND (
+                ?2 IS NULL
+                OR julianday(p.first_seen_at) > julianday(?2)
+                OR EXISTS (
+                    SELECT 1 FROM readd_history r
+                    WHERE r.owner_steam_id = p.owner_steam_id AND r.steam_id = p.steam_id AND julianday(r.readded_at) > julianday(?2)
+                )
+            )
+            ORDER BY p.steam_id"
+        )?;
+        let friends = stmt.query_map((self.owner, previous_sync_at), StoredFriend::from_row)?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(friends)
+    }
+
+    /// Every name `steam_id` has gone by, oldest first, with when we last saw them using it.
+    pub fn get_name_history(&self, steam_id: SteamId) -> Result<Vec<(String, DateTime<Utc>)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT persona_name, updated_at FROM name_history WHERE steam_id = ? ORDER BY updated_at, rowid"
+        )?;
+        let names = stmt.query_map([steam_id], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(names)
+    }
+
+    /// Same as [`Self::get_name_history`], but only names seen at or after `since`.
+    pub fn get_name_history_since(&self, steam_id: SteamId, since: DateTime<Utc>) -> Result<Vec<(String, DateTime<Utc>)>, rusqlite::Error> {
+        // `CURRENT_TIMESTAMP` and a bound `DateTime` aren't formatted the same, so compare them as times rather than text
+        let mut stmt = self.conn.prepare(
+            "SELECT persona_name, updated_at
+            FROM name_history
+            WHERE steam_id = ? AND julianday(updated_at) >= julianday(?)
+            ORDER BY updated_at, rowid"
+        )?;
+        let names = stmt.query_map((steam_id, since), |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(names)
+    }
+
+    /// Active friends with the most entries in `name_history`, most first, along with that count.
+    pub fn most_name_changes(&self, limit: usize) -> Result<Vec<(StoredFriend, u32)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT p.steam_id, p.persona_name, p.profile_url, p.friend_since, p.updated_at, COUNT(*) AS names
+            FROM player_summaries p
+            JOIN name_history n ON n.steam_id = p.steam_id
+            WHERE p.owner_steam_id = ? AND p.removed_at IS NULL
+            GROUP BY p.steam_id
+            ORDER BY names DESC, p.steam_id
+            LIMIT ?"
+        )?;
+        let friends = stmt.query_map(
+            (self.owner, limit as i64),
+            |row| Ok((StoredFriend::from_row(row)?, row.get(5)?))
+        )?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(friends)
+    }
+
+    /// Active friends with a single entry in `name_history`, i.e. who haven't been renamed since tracking began.
+    pub fn stable_names(&self) -> Result<Vec<StoredFriend>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT p.steam_id, p.persona_name, p.profile_url, p.friend_since, p.updated_at
+            FROM player_summaries p
+            JOIN name_history n ON n.steam_id = p.steam_id
+            WHERE p.owner_steam_id = ? AND p.removed_at IS NULL
+            GROUP BY p.steam_id
+            HAVING COUNT(*) = 1
+            ORDER BY p.steam_id"
+        )?;
+        let friends = stmt.query_map([self.owner], StoredFriend::from_row)?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(friends)
+    }
+
+    /// Friends (current or removed) whose `friend_since` is before their account could have been created, going by
+    /// [`SteamId::account_creation_era`]. The eras are approximate, so treat accounts near an era's start as leads
+    /// rather than proof of bad data. Pending invites have no `friend_since`, so they're never included.
+    pub fn find_impossible_friendships(&self) -> Result<Vec<SteamId>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT steam_id, friend_since FROM player_summaries WHERE owner_steam_id = ? AND friend_since IS NOT NULL ORDER BY steam_id"
+        )?;
+        let friends = stmt.query_map([self.owner], |row| Ok((row.get::<_, SteamId>(0)?, row.get::<_, DateTime<Utc>>(1)?)))?;
+
+        friends
+            .filter_map(|row| match row {
+                Ok((steam_id, friend_since)) if friend_since.year() >= steam_id.account_creation_era().earliest_year() => None,
+                Ok((steam_id, _)) => Some(Ok(steam_id)),
+                Err(e) => Some(Err(e)),
+            })
+            .collect()
+    }
+
+    /// `json_path` (e.g. `$.avatarfull`) out of `steam_id`'s raw summary. `None` if there's no such field, or no raw
+    /// summary was stored, see [`Self::set_store_raw_json`].
+    pub fn query_raw_field(&self, steam_id: SteamId, json_path: &str) -> Result<Option<serde_json::Value>, rusqlite::Error> {
+        // `json_quote` so plain strings come back as JSON too
+        let value = self.conn.query_row(
+            "SELECT json_quote(json_extract(raw_json, ?2))
+            FROM player_summaries
+            WHERE owner_steam_id = ?3 AND steam_id = ?1 AND raw_json IS NOT NULL",
+            (steam_id, json_path, self.owner),
+            |row| row.get::<_, serde_json::Value>(0)
+        ).optional()?;
+
+        Ok(value.filter(|v| !v.is_null()))
+    }
+
+    /// Current friends for the `export_*` methods. These are re-sorted here so exports stay byte-for-byte
+    /// reproducible (and diffable) no matter how the query above changes.
+    fn friends_for_export(&self) -> Result<Vec<StoredFriend>, rusqlite::Error> {
+        let mut friends = self.get_current_friends()?;
+        friends.sort_unstable_by_key(|f| f.steam_id);
+        Ok(friends)
+    }
+
+    /// Writes current friends as a pretty-printed JSON array, sorted by `steam_id`.
+    pub fn export_json<W: Write>(&self, mut writer: W) -> Result<(), ExportError> {
+        serde_json::to_writer_pretty(&mut writer, &self.friends_for_export()?)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Writes current friends as one JSON object per line, sorted by `steam_id`.
+    pub fn export_ndjson<W: Write>(&self, mut writer: W) -> Result<(), ExportError> {
+        for friend in self.friends_for_export()? {
+            serde_json::to_writer(&mut writer, &friend)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Writes current friends as CSV with a header row, sorted by `steam_id`.
+    pub fn export_csv<W: Write>(&self, writer: W) -> Result<(), ExportError> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for friend in self.friends_for_export()? {
+            writer.serialize(friend)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// App IDs owned by every one of `steam_ids`, in ascending order.
+    /// Anyone with no rows in `owned_games` (usually a private game library) is left out instead of
+    /// making the whole result empty. An empty `steam_ids` gives an empty result.
+    pub fn games_owned_by_all(&self, steam_ids: &[SteamId]) -> Result<Vec<AppId>, rusqlite::Error> {
+        // See `mark_removed` about `IN ()`
+        if steam_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let substitution_string = placeholders(steam_ids.len());
+        let query = format!(
+            "SELECT appid
+            FROM owned_games
+            WHERE steam_id IN ({0})
+            GROUP BY appid
+            HAVING COUNT(*) = (SELECT COUNT(DISTINCT steam_id) FROM owned_games WHERE steam_id IN ({0}))
+            ORDER BY appid
+        ",
+            substitution_string
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let app_ids = stmt.query_map(
+            rusqlite::params_from_iter(steam_ids.iter().chain(steam_ids)),
+            |row| row.get(0)
+        )?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(app_ids)
+    }
+
+    /// The game `steam_id` has played the most, by `playtime_forever`. `None` if we have no owned games for them.
+    pub fn favorite_game(&self, steam_id: SteamId) -> Result<Option<(AppId, Playtime)>, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT appid, playtime_forever
+            FROM owned_games
+            WHERE steam_id = ?
+            ORDER BY playtime_forever DESC, appid
+            LIMIT 1",
+            [steam_id],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        ).optional()
+    }
+
+    /// Counts friends first seen and removed within the last `window`, relative to who is active now.
+    pub fn churn_rate(&self, window: Duration) -> Result<ChurnStats, rusqlite::Error> {
+        self.churn_since(Utc::now() - window)
+    }
+
+    fn churn_since(&self, since: DateTime<Utc>) -> Result<ChurnStats, rusqlite::Error> {
+        // `julianday` since `CURRENT_TIMESTAMP` and chrono write timestamps differently
+        let (added, removed, active): (usize, usize, usize) = self.conn.query_row(
+            "SELECT
+                COUNT(*) FILTER (WHERE julianday(first_seen_at) >= julianday(?1)),
+                COUNT(*) FILTER (WHERE julianday(removed_at) >= julianday(?1)),
+                COUNT(*) FILTER (WHERE removed_at IS NULL)
+            FROM player_summaries
+            WHERE owner_steam_id = ?2",
+            (since, self.owner),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        )?;
+
+        let churn_pct = if active == 0 {
+            0.0
+        } else {
+            (added + removed) as f64 / active as f64 * 100.0
+        };
+
+        Ok(ChurnStats {
+            added,
+            removed,
+            net_change: added as i64 - removed as i64,
+            churn_pct,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::Duration;
+    use chrono::{DateTime, TimeZone, Utc};
+    use crate::steam_api::{Friend, Relationship};
+    use crate::steam_api::SteamId;
+    use super::*;
+
+    #[derive(Debug)]
+    struct PlayerSummariesRow {
+        steam_id: SteamId,
+        persona_name: String,
+        profile_url: String,
+        friend_since: Option<DateTime<Utc>>,
+        updated_at: DateTime<Utc>,
+        removed_at: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug)]
+    struct NameHistoryRow {
+        steam_id: SteamId,
+        persona_name: String,
+        updated_at: DateTime<Utc>,
+    }
+
+    fn friend(steam_id: i64) -> Friend {
+        Friend {
+            steam_id: SteamId(steam_id),
+            relationship: Relationship::Friend,
+            friend_since: Some(Utc::now()),
+        }
+    }
+
+    fn summary(steam_id: i64, persona_name: &str) -> PlayerSummary {
+        PlayerSummary {
+            steam_id: SteamId(steam_id),
+            persona_name: persona_name.to_string(),
+            profile_url: format!("{persona_name}_url"),
+            avatar: String::new(),
+            avatar_medium: String::new(),
+            avatar_full: String::new(),
+            persona_state: PersonaState::Offline,
+            last_logoff: None,
+            game_id: None,
+            game_extra_info: None,
+            raw_json: None,
+        }
     }
 
     #[test]
@@ -153,12 +1325,20 @@ mod tests {
         let mut friends = [Friend {
             steam_id: SteamId(1),
             relationship: Relationship::Friend,
-            friend_since: Utc::now(),
+            friend_since: Some(Utc::now()),
         }];
         let mut players = [PlayerSummary {
             steam_id: SteamId(1),
             persona_name: "one".to_string(),
             profile_url: "one_url".to_string(),
+            avatar: String::new(),
+            avatar_medium: String::new(),
+            avatar_full: String::new(),
+            persona_state: PersonaState::Offline,
+            last_logoff: None,
+            game_id: None,
+            game_extra_info: None,
+            raw_json: None,
         }];
         db.update_player_summaries(&mut friends, &mut players).unwrap();
 
@@ -200,12 +1380,20 @@ mod tests {
         let mut friends = [Friend {
             steam_id: SteamId(1),
             relationship: Relationship::Friend,
-            friend_since: Utc::now(),
+            friend_since: Some(Utc::now()),
         }];
         let mut players = [PlayerSummary {
             steam_id: SteamId(1),
             persona_name: "one".to_string(),
             profile_url: "one_url".to_string(),
+            avatar: String::new(),
+            avatar_medium: String::new(),
+            avatar_full: String::new(),
+            persona_state: PersonaState::Offline,
+            last_logoff: None,
+            game_id: None,
+            game_extra_info: None,
+            raw_json: None,
         }];
         db.update_player_summaries(&mut friends, &mut players).unwrap();
 
@@ -243,12 +1431,12 @@ mod tests {
             Friend {
                 steam_id: SteamId(1),
                 relationship: Relationship::Friend,
-                friend_since: now,
+                friend_since: Some(now),
             },
             Friend {
-                steam_id: SteamId(1),
+                steam_id: SteamId(2),
                 relationship: Relationship::Friend,
-                friend_since: now,
+                friend_since: Some(now),
             },
         ];
         let mut players = [
@@ -256,11 +1444,27 @@ mod tests {
                 steam_id: SteamId(1),
                 persona_name: "one".to_string(),
                 profile_url: "one_url".to_string(),
+                avatar: String::new(),
+                avatar_medium: String::new(),
+                avatar_full: String::new(),
+                persona_state: PersonaState::Offline,
+                last_logoff: None,
+                game_id: None,
+                game_extra_info: None,
+                raw_json: None,
             },
             PlayerSummary {
                 steam_id: SteamId(2),
                 persona_name: "two".to_string(),
                 profile_url: "two_url".to_string(),
+                avatar: String::new(),
+                avatar_medium: String::new(),
+                avatar_full: String::new(),
+                persona_state: PersonaState::Offline,
+                last_logoff: None,
+                game_id: None,
+                game_extra_info: None,
+                raw_json: None,
             },
         ];
         db.update_player_summaries(&mut friends, &mut players).unwrap();
@@ -304,7 +1508,7 @@ mod tests {
             Friend {
                 steam_id: SteamId(1),
                 relationship: Relationship::Friend,
-                friend_since: Utc::now(),
+                friend_since: Some(Utc::now()),
             },
         ];
         let mut players = [
@@ -312,6 +1516,14 @@ mod tests {
                 steam_id: SteamId(1),
                 persona_name: "one".to_string(),
                 profile_url: "one_url".to_string(),
+                avatar: String::new(),
+                avatar_medium: String::new(),
+                avatar_full: String::new(),
+                persona_state: PersonaState::Offline,
+                last_logoff: None,
+                game_id: None,
+                game_extra_info: None,
+                raw_json: None,
             },
         ];
         db.update_player_summaries(&mut friends, &mut players).unwrap();
@@ -354,4 +1566,1006 @@ mod tests {
         assert_eq!(players[0].steam_id, rows[1].steam_id);
         assert_eq!("one_updated".to_string(), rows[1].persona_name);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_churn_rate() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        let now = Utc::now();
+        let mut stmt = db.conn.prepare(
+            "INSERT INTO player_summaries
+                (steam_id, persona_name, profile_url, friend_since, first_seen_at, removed_at)
+            VALUES
+                (?, ?, ?, ?, ?, ?)
+            "
+        ).unwrap();
+        // Long-time friend, still here
+        stmt.execute((1, "one", "one_url", now, now - chrono::Duration::days(30), None::<DateTime<Utc>>)).unwrap();
+        // Two new friends this week
+        stmt.execute((2, "two", "two_url", now, now - chrono::Duration::days(1), None::<DateTime<Utc>>)).unwrap();
+        stmt.execute((3, "three", "three_url", now, now - chrono::Duration::days(2), None::<DateTime<Utc>>)).unwrap();
+        // Removed this week
+        stmt.execute((4, "four", "four_url", now, now - chrono::Duration::days(30), Some(now - chrono::Duration::days(1)))).unwrap();
+        // Removed long ago
+        stmt.execute((5, "five", "five_url", now, now - chrono::Duration::days(60), Some(now - chrono::Duration::days(30)))).unwrap();
+        drop(stmt);
+
+        let stats = db.churn_rate(chrono::Duration::days(7)).unwrap();
+
+        assert_eq!(2, stats.added);
+        assert_eq!(1, stats.removed);
+        assert_eq!(1, stats.net_change);
+        assert_eq!(100.0, stats.churn_pct);
+    }
+
+    #[test]
+    fn test_churn_rate_window_boundary() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        // Written like `CURRENT_TIMESTAMP` would, right at the window's start and a second before it
+        db.conn.execute(
+            "INSERT INTO player_summaries
+                (steam_id, persona_name, profile_url, friend_since, first_seen_at, removed_at)
+            VALUES
+                (1, 'one', 'one_url', 0, '2024-01-01 00:00:00', NULL),
+                (2, 'two', 'two_url', 0, '2023-12-31 23:59:59', NULL),
+                (3, 'three', 'three_url', 0, '2023-06-01 00:00:00', '2024-01-01 00:00:00'),
+                (4, 'four', 'four_url', 0, '2023-06-01 00:00:00', '2023-12-31 23:59:59')
+            ",
+            ()
+        ).unwrap();
+
+        let stats = db.churn_since(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()).unwrap();
+
+        assert_eq!(1, stats.added);
+        assert_eq!(1, stats.removed);
+    }
+
+    #[test]
+    fn test_metrics_text() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO player_summaries
+                (steam_id, persona_name, profile_url, friend_since, removed_at)
+            VALUES
+                (1, 'one', 'one_url', CURRENT_TIMESTAMP, NULL),
+                (2, 'two', 'two_url', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+            ",
+            ()
+        ).unwrap();
+        db.record_sync(Utc::now(), Duration::from_millis(1500)).unwrap();
+
+        let text = db.metrics_text().unwrap();
+
+        for name in [
+            "steam_friends_total",
+            "steam_friends_active",
+            "steam_last_sync_timestamp_seconds",
+            "steam_last_sync_duration_seconds",
+        ] {
+            assert!(text.contains(&format!("# TYPE {name} gauge\n")), "{name} missing from:\n{text}");
+        }
+        for line in text.lines().filter(|l| !l.starts_with('#')) {
+            let (name, value) = line.split_once(' ').unwrap();
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "Bad metric name in {line}");
+            assert!(value.parse::<f64>().is_ok(), "Bad metric value in {line}");
+        }
+        assert!(text.contains("steam_friends_total 2\n"));
+        assert!(text.contains("steam_friends_active 1\n"));
+        assert!(text.contains("steam_last_sync_duration_seconds 1.5\n"));
+    }
+
+    #[test]
+    fn test_games_owned_by_all() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO owned_games
+                (steam_id, appid, playtime_forever)
+            VALUES
+                (1, 10, 0), (1, 20, 0), (1, 30, 0),
+                (2, 20, 0), (2, 30, 0), (2, 40, 0),
+                (3, 30, 0), (3, 20, 0), (3, 50, 0)
+            ",
+            ()
+        ).unwrap();
+
+        // 4 has no owned games data, so they shouldn't narrow anything down
+        let games = db.games_owned_by_all(&[SteamId(1), SteamId(2), SteamId(3), SteamId(4)]).unwrap();
+        assert_eq!(vec![AppId(20), AppId(30)], games);
+
+        let games = db.games_owned_by_all(&[SteamId(1), SteamId(2)]).unwrap();
+        assert_eq!(vec![AppId(20), AppId(30)], games);
+
+        let games = db.games_owned_by_all(&[SteamId(1)]).unwrap();
+        assert_eq!(vec![AppId(10), AppId(20), AppId(30)], games);
+
+        assert!(db.games_owned_by_all(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_exports_are_sorted_and_reproducible() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO player_summaries
+                (steam_id, persona_name, profile_url, friend_since)
+            VALUES
+                (3, 'three', 'three_url', CURRENT_TIMESTAMP),
+                (1, 'one', 'one_url', CURRENT_TIMESTAMP),
+                (2, 'two', 'two_url', CURRENT_TIMESTAMP)
+            ",
+            ()
+        ).unwrap();
+
+        let exports: [fn(&DbConnection, &mut Vec<u8>) -> Result<(), ExportError>; 3] = [
+            |db, out| db.export_json(out),
+            |db, out| db.export_ndjson(out),
+            |db, out| db.export_csv(out),
+        ];
+        for export in exports {
+            let mut first = Vec::new();
+            let mut second = Vec::new();
+            export(&db, &mut first).unwrap();
+            export(&db, &mut second).unwrap();
+            assert_eq!(first, second);
+
+            let text = String::from_utf8(first).unwrap();
+            let one = text.find("one_url").unwrap();
+            let two = text.find("two_url").unwrap();
+            let three = text.find("three_url").unwrap();
+            assert!(one < two && two < three, "Export wasn't sorted by steam_id:\n{text}");
+        }
+    }
+
+    #[test]
+    fn test_friends_added_between() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let since = |secs| Friend { friend_since: Utc.timestamp_opt(secs, 0).single(), ..friend(secs) };
+        db.update_player_summaries(
+            &mut [since(100), since(200), since(300)],
+            &mut [summary(100, "one"), summary(200, "two"), summary(300, "three")],
+        ).unwrap();
+
+        let friends = db.friends_added_between(Utc.timestamp_opt(100, 0).unwrap(), Utc.timestamp_opt(300, 0).unwrap()).unwrap();
+
+        assert_eq!(vec![SteamId(100), SteamId(200)], friends.iter().map(|f| f.steam_id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_friends_added_in_last_sync() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        assert_eq!(2, db.friends_added_in_last_sync().unwrap().len());
+        // As if that sync was a while ago
+        db.conn.execute("UPDATE player_summaries SET first_seen_at = '2020-01-01 00:00:00', updated_at = '2020-01-01 00:00:00'", ()).unwrap();
+
+        db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3)],
+            &mut [summary(1, "one"), summary(2, "two_renamed"), summary(3, "three")],
+        ).unwrap();
+
+        let friends = db.friends_added_in_last_sync().unwrap();
+        assert_eq!(vec![SteamId(3)], friends.iter().map(|f| f.steam_id).collect::<Vec<_>>());
+
+        // 2 leaves, then comes back in the latest sync, which counts even though they were first seen long ago
+        db.update_player_summaries(&mut [friend(1), friend(3)], &mut [summary(1, "one"), summary(3, "three")]).unwrap();
+        db.conn.execute("UPDATE player_summaries SET first_seen_at = '2020-01-01 00:00:00', updated_at = '2020-01-02 00:00:00'", ()).unwrap();
+        db.conn.execute("UPDATE player_summaries SET removed_at = '2020-01-02 00:00:00' WHERE steam_id = 2", ()).unwrap();
+        db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3)],
+            &mut [summary(1, "one"), summary(2, "two_renamed"), summary(3, "three")],
+        ).unwrap();
+
+        let friends = db.friends_added_in_last_sync().unwrap();
+        assert_eq!(vec![SteamId(2)], friends.iter().map(|f| f.steam_id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_unusual_names_round_trip() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        // An emoji, a zero-width space, a right-to-left override, and a replaced lone surrogate
+        let name = "🎮 Ga\u{200B}be \u{202E}llewen\u{FFFD}";
+
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, name)]).unwrap();
+
+        assert_eq!(name, db.get_current_friends().unwrap()[0].persona_name);
+        assert_eq!(name, db.get_name_history(SteamId(1)).unwrap()[0].0);
+    }
+
+    #[test]
+    fn test_record_presence() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let in_state = |steam_id, persona_state| PlayerSummary { persona_state, ..summary(steam_id, "name") };
+
+        assert_eq!(2, db.record_presence(&[in_state(1, PersonaState::Offline), in_state(2, PersonaState::Online)]).unwrap());
+        // Nothing changed
+        assert_eq!(0, db.record_presence(&[in_state(1, PersonaState::Offline), in_state(2, PersonaState::Online)]).unwrap());
+        assert_eq!(1, db.record_presence(&[in_state(1, PersonaState::Away), in_state(2, PersonaState::Online)]).unwrap());
+        assert_eq!(1, db.record_presence(&[in_state(1, PersonaState::Offline)]).unwrap());
+
+        let states = db.presence_history(SteamId(1)).unwrap().into_iter().map(|(_, state)| state).collect::<Vec<_>>();
+        assert_eq!(vec![PersonaState::Offline, PersonaState::Away, PersonaState::Offline], states);
+        assert_eq!(1, db.presence_history(SteamId(2)).unwrap().len());
+    }
+
+    #[test]
+    fn test_prune_removed() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3)],
+            &mut [summary(1, "one"), summary(2, "two"), summary(3, "three")],
+        ).unwrap();
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+        db.conn.execute("UPDATE player_summaries SET removed_at = datetime('now', '-400 days') WHERE steam_id = 2", ()).unwrap();
+
+        assert_eq!(1, db.prune_removed(chrono::Duration::days(365)).unwrap());
+
+        let remaining = db.conn.prepare("SELECT steam_id FROM player_summaries ORDER BY steam_id").unwrap()
+            .query_map((), |row| row.get(0)).unwrap()
+            .collect::<Result<Vec<SteamId>, _>>().unwrap();
+        assert_eq!(vec![SteamId(1), SteamId(3)], remaining);
+        assert!(db.get_name_history(SteamId(2)).unwrap().is_empty());
+        assert_eq!(1, db.get_name_history(SteamId(3)).unwrap().len());
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO player_summaries
+                (steam_id, persona_name, profile_url, friend_since, updated_at, removed_at)
+            VALUES
+                (1, 'one', 'one_url', '2020-01-01 00:00:00', '2024-06-01 12:00:00', NULL),
+                (2, 'two', 'two_url', '2020-01-01 00:00:00', '2024-06-01 12:00:00', '2024-06-01 12:00:00')
+            ",
+            ()
+        ).unwrap();
+
+        let mut out = Vec::new();
+        db.export_csv(&mut out).unwrap();
+
+        // Removed friends are left out
+        assert_eq!(
+            "steam_id,persona_name,profile_url,friend_since,updated_at\n\
+             1,one,one_url,2020-01-01T00:00:00Z,2024-06-01T12:00:00Z\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_player_summaries_readd() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+        let removed_at: Option<DateTime<Utc>> = db.conn.query_row(
+            "SELECT removed_at FROM player_summaries WHERE steam_id = 2",
+            (),
+            |row| row.get(0)
+        ).unwrap();
+        assert!(removed_at.is_some());
+
+        // They're back!
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        let readded_removed_at: Option<DateTime<Utc>> = db.conn.query_row(
+            "SELECT removed_at FROM player_summaries WHERE steam_id = 2",
+            (),
+            |row| row.get(0)
+        ).unwrap();
+        assert_eq!(None, readded_removed_at);
+
+        let readds: Vec<(SteamId, DateTime<Utc>)> = db.conn.prepare("SELECT steam_id, removed_at FROM readd_history")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec![(SteamId(2), removed_at.unwrap())], readds);
+    }
+
+    #[test]
+    fn test_update_player_summaries_with_nobody_left() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+
+        let report = db.update_player_summaries(&mut [], &mut []).unwrap();
+
+        assert_eq!(SyncReport { added: 0, removed: 2, renamed: 0, total_current: 0 }, report);
+        assert!(db.get_current_friends().unwrap().is_empty());
+        assert_eq!(0, db.mark_removed(&[]).unwrap());
+    }
+
+    #[test]
+    fn test_update_player_summaries_rolls_back_removals() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        // Fails the upserts, which run after 2 is marked removed
+        db.conn.execute_batch(
+            "CREATE TEMP TRIGGER fail_upsert BEFORE INSERT ON name_history BEGIN SELECT RAISE(ABORT, 'boom'); END"
+        ).unwrap();
+
+        assert!(db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).is_err());
+
+        assert_eq!(2, db.count_current_friends().unwrap());
+        assert!(db.conn.is_autocommit());
+    }
+
+    #[test]
+    fn test_mark_removed_keeps_name_at_removal() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+        // Back again, but under a different name
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two_renamed")]).unwrap();
+
+        let (persona_name, removed_with_name): (String, Option<String>) = db.conn.query_row(
+            "SELECT persona_name, removed_with_name FROM player_summaries WHERE steam_id = 2",
+            (),
+            |row| Ok((row.get(0)?, row.get(1)?))
+        ).unwrap();
+        assert_eq!("two_renamed", persona_name);
+        assert_eq!(Some("two".to_string()), removed_with_name);
+
+        let never_removed: Option<String> = db.conn.query_row(
+            "SELECT removed_with_name FROM player_summaries WHERE steam_id = 1",
+            (),
+            |row| row.get(0)
+        ).unwrap();
+        assert_eq!(None, never_removed);
+    }
+
+    #[test]
+    fn test_wal_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.db");
+        let db = DbConnection::new(&path).unwrap();
+
+        let journal_mode: String = db.conn.query_row("PRAGMA journal_mode", (), |row| row.get(0)).unwrap();
+        assert_eq!("wal", journal_mode);
+        let busy_timeout: u64 = db.conn.query_row("PRAGMA busy_timeout", (), |row| row.get(0)).unwrap();
+        assert_eq!(BUSY_TIMEOUT.as_millis() as u64, busy_timeout);
+
+    }
+
+    #[test]
+    fn test_corrupted_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrupted.db");
+        std::fs::write(&path, [0xAB; 4096]).unwrap();
+
+        let res = DbConnection::new(&path);
+        assert!(matches!(res, Err(DbError::Corrupted { path: ref p }) if *p == path), "{:?}", res.err());
+
+        let db = DbConnection::open_or_recover(&path).unwrap();
+        db.create_tables().unwrap();
+        drop(db);
+
+        let mut corrupt_path = path.as_os_str().to_owned();
+        corrupt_path.push(".corrupt");
+        assert_eq!(vec![0xAB; 4096], std::fs::read(&corrupt_path).unwrap());
+    }
+
+    #[test]
+    fn test_most_name_changes() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3), friend(4)],
+            &mut [summary(1, "one"), summary(2, "two"), summary(3, "three"), summary(4, "four")]
+        ).unwrap();
+        db.conn.execute(
+            "INSERT INTO name_history
+                (steam_id, persona_name)
+            VALUES
+                (1, 'one_b'), (1, 'one_c'),
+                (3, 'three_b'),
+                (4, 'four_b'), (4, 'four_c'), (4, 'four_d')
+            ",
+            ()
+        ).unwrap();
+        // 4 has the most names, but isn't a friend anymore
+        db.conn.execute("UPDATE player_summaries SET removed_at = CURRENT_TIMESTAMP WHERE steam_id = 4", ()).unwrap();
+
+        let ranked = db.most_name_changes(2).unwrap()
+            .into_iter()
+            .map(|(friend, names)| (friend.steam_id, names))
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![(SteamId(1), 3), (SteamId(3), 2)], ranked);
+    }
+
+    #[test]
+    fn test_repair_history_consistency() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        // Simulate a bad manual edit: 1's current name never made it into the history
+        db.conn.execute("UPDATE player_summaries SET persona_name = 'one_edited' WHERE steam_id = 1", ()).unwrap();
+
+        assert_eq!(1, db.repair_history_consistency().unwrap());
+        // Nothing left to fix
+        assert_eq!(0, db.repair_history_consistency().unwrap());
+
+        let names: Vec<String> = db.conn.prepare("SELECT persona_name FROM name_history WHERE steam_id = 1 ORDER BY persona_name")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec!["one".to_string(), "one_edited".to_string()], names);
+    }
+
+    #[test]
+    fn test_favorite_game() {
+        let db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO owned_games
+                (steam_id, appid, playtime_forever)
+            VALUES
+                (1, 10, 120), (1, 20, 6000), (1, 30, 45),
+                (2, 10, 9999)
+            ",
+            ()
+        ).unwrap();
+
+        assert_eq!(Some((AppId(20), Playtime(6000))), db.favorite_game(SteamId(1)).unwrap());
+        assert_eq!(None, db.favorite_game(SteamId(3)).unwrap());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+
+        let diagnostics = db.diagnostics().unwrap();
+
+        assert!(!diagnostics.sqlite_version.is_empty());
+        assert_eq!(None, diagnostics.size_bytes);
+        let tables = diagnostics.tables.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        for table in ["name_history", "owned_games", "player_summaries", "readd_history", "sync_metadata"] {
+            assert!(tables.contains(&table), "{table} missing from {tables:?}");
+        }
+        assert!(diagnostics.tables.contains(&("player_summaries".to_string(), 2)));
+        assert!(diagnostics.to_string().contains("player_summaries: 2 rows"));
+    }
+
+    #[test]
+    fn test_diagnostics_size_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diagnostics.db");
+        let mut db = DbConnection::new(&path).unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+
+        // Still open, so the latest writes are in the WAL and count towards the size
+        let wal_path = format!("{}-wal", path.display());
+        let wal_bytes = std::fs::metadata(&wal_path).unwrap().len();
+        assert!(wal_bytes > 0);
+        let expected = std::fs::metadata(&path).unwrap().len() + wal_bytes;
+        assert_eq!(Some(expected), db.diagnostics().unwrap().size_bytes);
+    }
+
+    #[test]
+    fn test_update_player_summaries_if_changed_skips_identical_data() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let mut friends = vec![friend(1), friend(2)];
+        let mut summaries = vec![summary(2, "two"), summary(1, "one")];
+
+        assert!(db.update_player_summaries_if_changed(&mut friends, &mut summaries).unwrap().is_some());
+        // Same data in a different order is still the same data
+        friends.reverse();
+        summaries.reverse();
+        let total_changes = |db: &DbConnection| db.conn().query_row("SELECT total_changes()", (), |row| row.get::<_, i64>(0)).unwrap();
+        let changes_before = total_changes(&db);
+        let skipped_at = Utc::now();
+        assert_eq!(None, db.update_player_summaries_if_changed(&mut friends, &mut summaries).unwrap());
+        // Nothing but the sync time, which still gets recorded
+        assert_eq!(changes_before + 1, total_changes(&db));
+        let last_sync_at = db.get_sync_metadata::<DateTime<Utc>>("last_sync_at").unwrap().unwrap();
+        assert!(last_sync_at >= skipped_at, "{last_sync_at} is before the sync at {skipped_at}");
+
+        summaries[0].persona_name = "new name".to_string();
+        assert_eq!(
+            Some(SyncReport { added: 0, removed: 0, renamed: 1, total_current: 2 }),
+            db.update_player_summaries_if_changed(&mut friends, &mut summaries).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_stable_names() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3), friend(4)],
+            &mut [summary(1, "one"), summary(2, "two"), summary(3, "three"), summary(4, "four")]
+        ).unwrap();
+        db.conn.execute("INSERT INTO name_history (steam_id, persona_name) VALUES (1, 'one_b'), (3, 'three_b')", ()).unwrap();
+        // 4 never changed their name, but isn't a friend anymore
+        db.conn.execute("UPDATE player_summaries SET removed_at = CURRENT_TIMESTAMP WHERE steam_id = 4", ()).unwrap();
+
+        let stable = db.stable_names().unwrap()
+            .into_iter()
+            .map(|friend| friend.steam_id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![SteamId(2)], stable);
+    }
+
+    #[test]
+    fn test_find_impossible_friendships() {
+        // Account IDs in the 2020+ range
+        let new_account = SteamId(76561197960265728 + 1_500_000_000);
+        let other_new_account = SteamId(76561197960265728 + 1_500_000_001);
+        let friend_since = |year| Utc.with_ymd_and_hms(year, 6, 1, 0, 0, 0).unwrap();
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(
+            &mut [
+                Friend { steam_id: SteamId(1), relationship: Relationship::Friend, friend_since: Some(friend_since(2008)) },
+                Friend { steam_id: new_account, relationship: Relationship::Friend, friend_since: Some(friend_since(2010)) },
+                Friend { steam_id: other_new_account, relationship: Relationship::Friend, friend_since: Some(friend_since(2021)) },
+            ],
+            &mut [
+                summary(1, "old"),
+                summary(new_account.0, "impossible"),
+                summary(other_new_account.0, "fine"),
+            ]
+        ).unwrap();
+
+        assert_eq!(vec![new_account], db.find_impossible_friendships().unwrap());
+    }
+
+    #[test]
+    fn test_query_raw_field() {
+        let raw = r#"{"steamid":"1","personaname":"one","profileurl":"one_url","avatarfull":"avatar.jpg","communityvisibilitystate":3}"#;
+        let mut with_raw = summary(1, "one");
+        with_raw.raw_json = Some(raw.to_string());
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        // Not stored unless asked for
+        db.update_player_summaries(&mut [friend(1)], &mut [with_raw]).unwrap();
+        assert_eq!(None, db.query_raw_field(SteamId(1), "$.avatarfull").unwrap());
+
+        let mut with_raw = summary(1, "one");
+        with_raw.raw_json = Some(raw.to_string());
+        db.set_store_raw_json(true);
+        db.update_player_summaries(&mut [friend(1)], &mut [with_raw]).unwrap();
+
+        assert_eq!(Some(serde_json::json!("avatar.jpg")), db.query_raw_field(SteamId(1), "$.avatarfull").unwrap());
+        assert_eq!(Some(serde_json::json!(3)), db.query_raw_field(SteamId(1), "$.communityvisibilitystate").unwrap());
+        assert_eq!(None, db.query_raw_field(SteamId(1), "$.not_a_field").unwrap());
+        assert_eq!(None, db.query_raw_field(SteamId(2), "$.avatarfull").unwrap());
+    }
+
+    #[test]
+    fn test_friend_count_series() {
+        let root = SteamId(100);
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.set_owner(root).unwrap();
+
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        assert_eq!(2, db.record_friend_count().unwrap());
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+        assert_eq!(1, db.record_friend_count().unwrap());
+
+        let counts = db.friend_count_series(root).unwrap()
+            .into_iter()
+            .map(|(_, count)| count)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![2, 1], counts);
+        assert!(db.friend_count_series(SteamId(200)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cache_app_names() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.cache_app_names(&[
+            App { app_id: AppId(440), name: "Team Fortress 2".to_string() },
+            App { app_id: AppId(570), name: "Dota 2".to_string() },
+        ]).unwrap();
+        db.cache_app_names(&[App { app_id: AppId(440), name: "Team Fortress 2 (Renamed)".to_string() }]).unwrap();
+
+        assert_eq!(Some("Team Fortress 2 (Renamed)".to_string()), db.app_name(AppId(440)).unwrap());
+        assert_eq!(Some("Dota 2".to_string()), db.app_name(AppId(570)).unwrap());
+        assert_eq!(None, db.app_name(AppId(1)).unwrap());
+    }
+
+    #[test]
+    fn test_update_owned_games() {
+        let games = |playtime| [
+            OwnedGame { app_id: AppId(440), name: "Team Fortress 2".to_string(), playtime_forever: Playtime(playtime), playtime_2weeks: None },
+            OwnedGame { app_id: AppId(570), name: "Dota 2".to_string(), playtime_forever: Playtime(10), playtime_2weeks: None },
+        ];
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        db.update_owned_games(SteamId(1), &games(100)).unwrap();
+        // CURRENT_TIMESTAMP only has second precision
+        sleep(Duration::from_secs(1));
+        db.update_owned_games(SteamId(1), &games(160)).unwrap();
+
+        let mut stmt = db.conn.prepare(
+            "SELECT playtime_forever FROM playtime_history WHERE steam_id = 1 AND appid = 440 ORDER BY recorded_at"
+        ).unwrap();
+        let history = stmt.query_map((), |row| row.get::<_, u32>(0)).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(vec![100, 160], history);
+
+        let history_rows: usize = db.conn.query_row("SELECT COUNT(*) FROM playtime_history", (), |row| row.get(0)).unwrap();
+        assert_eq!(4, history_rows);
+        assert_eq!(Some((AppId(440), Playtime(160))), db.favorite_game(SteamId(1)).unwrap());
+    }
+
+    #[test]
+    fn test_get_current_friends_skips_removed() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(2), friend(1)], &mut [summary(2, "two"), summary(1, "one")]).unwrap();
+        db.update_player_summaries(&mut [friend(2)], &mut [summary(2, "two")]).unwrap();
+
+        let friends = db.get_current_friends().unwrap();
+
+        assert_eq!(1, friends.len());
+        assert_eq!(SteamId(2), friends[0].steam_id);
+        assert_eq!("two", friends[0].persona_name);
+        assert_eq!("two_url", friends[0].profile_url);
+    }
+
+    #[test]
+    fn test_get_name_history() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO name_history
+                (steam_id, persona_name, updated_at)
+            VALUES
+                (1, 'newer', '2024-02-01 00:00:00'),
+                (1, 'older', '2024-01-01 00:00:00'),
+                (2, 'someone else', '2024-01-15 00:00:00')
+            ",
+            ()
+        ).unwrap();
+
+        let history = db.get_name_history(SteamId(1)).unwrap();
+
+        assert_eq!(
+            vec![
+                ("older".to_string(), Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+                ("newer".to_string(), Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap()),
+            ],
+            history
+        );
+    }
+
+    #[test]
+    fn test_get_name_history_since() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.conn.execute(
+            "INSERT INTO name_history
+                (steam_id, persona_name, updated_at)
+            VALUES
+                (1, 'oldest', '2023-12-31 23:59:59'),
+                (1, 'boundary', '2024-01-01 00:00:00'),
+                (1, 'newest', '2024-02-01 00:00:00')
+            ",
+            ()
+        ).unwrap();
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "just now")]).unwrap();
+
+        let names = |since| db.get_name_history_since(SteamId(1), since).unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["boundary", "newest", "just now"], names(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()));
+        assert_eq!(vec!["just now"], names(Utc::now() - chrono::Duration::minutes(1)));
+        assert!(names(Utc::now() + chrono::Duration::minutes(1)).is_empty());
+    }
+
+    #[test]
+    fn test_update_player_summaries_missing_summary() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let friend_since = |steam_id| Utc.timestamp_opt(1_500_000_000 + steam_id, 0).unwrap();
+        let mut friends = (1..=3)
+            .map(|steam_id| Friend { steam_id: SteamId(steam_id), relationship: Relationship::Friend, friend_since: Some(friend_since(steam_id)) })
+            .collect::<Vec<_>>();
+
+        // No summary for 2, and 3's comes back first
+        db.update_player_summaries(&mut friends, &mut [summary(3, "three"), summary(1, "one")]).unwrap();
+
+        let mut stmt = db.conn.prepare("SELECT steam_id, persona_name, friend_since FROM player_summaries ORDER BY steam_id").unwrap();
+        let rows = stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(SteamId, String, DateTime<Utc>)>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (SteamId(1), "one".to_string(), friend_since(1)),
+                (SteamId(3), "three".to_string(), friend_since(3)),
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn test_update_player_summaries_duplicate_friends() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let earlier = Utc.with_ymd_and_hms(2015, 1, 1, 0, 0, 0).unwrap();
+        let later = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let mut friends = [
+            Friend { steam_id: SteamId(1), relationship: Relationship::Friend, friend_since: Some(later) },
+            Friend { steam_id: SteamId(2), relationship: Relationship::Friend, friend_since: Some(later) },
+            Friend { steam_id: SteamId(1), relationship: Relationship::Friend, friend_since: Some(earlier) },
+        ];
+
+        db.update_player_summaries(&mut friends, &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+
+        let mut stmt = db.conn.prepare("SELECT steam_id, persona_name, friend_since FROM player_summaries ORDER BY steam_id").unwrap();
+        let rows = stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(SteamId, String, DateTime<Utc>)>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (SteamId(1), "one".to_string(), earlier),
+                (SteamId(2), "two".to_string(), later),
+            ],
+            rows
+        );
+        let names: usize = db.conn.query_row("SELECT COUNT(*) FROM name_history", (), |row| row.get(0)).unwrap();
+        assert_eq!(2, names);
+    }
+
+    #[test]
+    fn test_preview_player_summaries() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3)],
+            &mut [summary(1, "one"), summary(2, "two"), summary(3, "three")]
+        ).unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+
+        // 1 is unchanged, 2 renamed, 3 back, 4 new, and 5 has a summary but isn't a friend
+        let friends = [friend(1), friend(2), friend(3), friend(4)];
+        let summaries = [summary(1, "one"), summary(2, "two_renamed"), summary(3, "three"), summary(4, "four"), summary(5, "five")];
+        let plan = db.preview_player_summaries(&friends, &summaries).unwrap();
+        assert_eq!(
+            UpdatePlan {
+                removed: vec![],
+                added: vec![SteamId(3), SteamId(4)],
+                renamed: vec![SteamId(2)],
+            },
+            plan
+        );
+
+        let plan = db.preview_player_summaries(&[friend(2)], &[summary(2, "two")]).unwrap();
+        assert_eq!(vec![SteamId(1)], plan.removed);
+        // Nothing was written
+        assert_eq!(2, db.get_current_friends().unwrap().len());
+    }
+
+    #[test]
+    fn test_update_player_summaries_report() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let report = db.update_player_summaries(
+            &mut [friend(1), friend(2), friend(3)],
+            &mut [summary(1, "one"), summary(2, "two"), summary(3, "three")]
+        ).unwrap();
+        assert_eq!(SyncReport { added: 3, removed: 0, renamed: 0, total_current: 3 }, report);
+
+        // 1 is gone, 2 renamed, 3 unchanged, 4 new
+        let report = db.update_player_summaries(
+            &mut [friend(2), friend(3), friend(4)],
+            &mut [summary(2, "two_renamed"), summary(3, "three"), summary(4, "four")]
+        ).unwrap();
+        assert_eq!(SyncReport { added: 1, removed: 1, renamed: 1, total_current: 3 }, report);
+        assert_eq!(r#"{"added":1,"removed":1,"renamed":1,"total_current":3}"#, serde_json::to_string(&report).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_original_schema() {
+        let db = DbConnection::new(":memory:").unwrap();
+        // The very first schema, before there were any versions
+        db.conn.execute(
+            "CREATE TABLE player_summaries (
+                steam_id INT8 PRIMARY KEY NOT NULL,
+                persona_name TEXT NOT NULL,
+                profile_url TEXT NOT NULL,
+                friend_since TIMESTAMP NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                removed_at TIMESTAMP
+            )",
+            ()
+        ).unwrap();
+        let friend_since = Utc.with_ymd_and_hms(2015, 1, 1, 0, 0, 0).unwrap();
+        db.conn.execute(
+            "INSERT INTO player_summaries (steam_id, persona_name, profile_url, friend_since, removed_at) VALUES (1, 'one', 'url', ?1, ?1)",
+            [friend_since]
+        ).unwrap();
+
+        db.create_tables().unwrap();
+
+        assert_eq!(MIGRATIONS.len() as i64, db.diagnostics().unwrap().schema_version);
+        let row: (String, String, DateTime<Utc>, Option<DateTime<Utc>>, String) = db.conn.query_row(
+            "SELECT persona_name, profile_url, friend_since, removed_at, relationship FROM player_summaries WHERE steam_id = 1",
+            (),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        ).unwrap();
+        assert_eq!(("one".to_string(), "url".to_string(), friend_since, Some(friend_since), "friend".to_string()), row);
+        // New rows get the defaults that came with the rebuild
+        db.conn.execute("INSERT INTO player_summaries (steam_id, persona_name, profile_url, friend_since) VALUES (2, 'two', '', 0)", ()).unwrap();
+        let first_seen_at: Option<DateTime<Utc>> = db.conn.query_row(
+            "SELECT first_seen_at FROM player_summaries WHERE steam_id = 2",
+            (),
+            |row| row.get(0)
+        ).unwrap();
+        assert!(first_seen_at.is_some());
+        // And pending invites can go in without a `friend_since`
+        db.conn.execute("INSERT INTO player_summaries (steam_id, persona_name, profile_url) VALUES (3, 'three', '')", ()).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_is_idempotent() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        // A new DB starts out at the latest version without running anything
+        assert_eq!(MIGRATIONS.len(), db.migrate().unwrap());
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+
+        db.create_tables().unwrap();
+
+        assert_eq!(MIGRATIONS.len(), db.migrate().unwrap());
+        assert_eq!(1, db.get_current_friends().unwrap().len());
+    }
+
+    #[test]
+    fn test_owners_are_kept_apart() {
+        let (a, b) = (SteamId(100), SteamId(200));
+        let current = |db: &DbConnection| db.get_current_friends().unwrap().iter().map(|f| f.steam_id.0).collect::<Vec<_>>();
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        db.set_owner(a).unwrap();
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        db.set_owner(b).unwrap();
+        // Syncing B doesn't remove A's friends, even the one they have in common
+        let report = db.update_player_summaries(&mut [friend(2), friend(3)], &mut [summary(2, "two"), summary(3, "three")]).unwrap();
+        assert_eq!(SyncReport { added: 2, removed: 0, renamed: 0, total_current: 2 }, report);
+        assert_eq!(vec![2, 3], current(&db));
+
+        db.set_owner(a).unwrap();
+        assert_eq!(vec![1, 2], current(&db));
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+        assert_eq!(vec![1], current(&db));
+        db.update_player_summaries(&mut [friend(1), friend(2)], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        db.set_owner(b).unwrap();
+        assert_eq!(vec![2, 3], current(&db));
+        assert_eq!(2, db.record_friend_count().unwrap());
+        // Kept under whoever the owner was at the time
+        assert_eq!(1, db.friend_count_series(b).unwrap().len());
+        assert!(db.friend_count_series(a).unwrap().is_empty());
+
+        let readd_owners: Vec<SteamId> = db.conn.prepare("SELECT owner_steam_id FROM readd_history")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec![a], readd_owners);
+    }
+
+    #[test]
+    fn test_first_owner_takes_over_unowned_rows() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.update_player_summaries(&mut [friend(1)], &mut [summary(1, "one")]).unwrap();
+
+        db.set_owner(SteamId(100)).unwrap();
+        assert_eq!(1, db.get_current_friends().unwrap().len());
+        db.set_owner(SteamId(200)).unwrap();
+        assert!(db.get_current_friends().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_relationship_column() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        // `player_summaries` as it was before `relationship`
+        db.conn.execute(
+            "CREATE TABLE player_summaries (
+                steam_id INT8 PRIMARY KEY NOT NULL,
+                persona_name TEXT NOT NULL,
+                profile_url TEXT NOT NULL,
+                friend_since TIMESTAMP NOT NULL,
+                first_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                removed_at TIMESTAMP,
+                removed_with_name TEXT,
+                raw_json TEXT
+            )",
+            ()
+        ).unwrap();
+        db.conn.execute("INSERT INTO player_summaries (steam_id, persona_name, profile_url, friend_since) VALUES (1, 'one', '', 0)", ()).unwrap();
+        db.create_tables().unwrap();
+        // Running it again doesn't try to add the column twice
+        db.create_tables().unwrap();
+
+        let relationship = |db: &DbConnection, steam_id: i64| -> String {
+            db.conn.query_row("SELECT relationship FROM player_summaries WHERE steam_id = ?", [steam_id], |row| row.get(0)).unwrap()
+        };
+        assert_eq!("friend", relationship(&db, 1));
+
+        let mut invite = friend(2);
+        invite.relationship = Relationship::All;
+        db.update_player_summaries(&mut [friend(1), invite], &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        assert_eq!("friend", relationship(&db, 1));
+        assert_eq!("all", relationship(&db, 2));
+    }
+
+    #[test]
+    fn test_update_player_summaries_keeps_friend_since() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let first_seen = Utc.with_ymd_and_hms(2015, 1, 1, 0, 0, 0).unwrap();
+        let drifted = Utc.with_ymd_and_hms(2021, 6, 1, 0, 0, 0).unwrap();
+        let friend_since = |friend_since| [Friend { steam_id: SteamId(1), relationship: Relationship::Friend, friend_since: Some(friend_since) }];
+
+        db.update_player_summaries(&mut friend_since(first_seen), &mut [summary(1, "one")]).unwrap();
+        db.update_player_summaries(&mut friend_since(drifted), &mut [summary(1, "one")]).unwrap();
+
+        let stored: DateTime<Utc> = db.conn.query_row(
+            "SELECT friend_since FROM player_summaries WHERE steam_id = 1",
+            (),
+            |row| row.get(0)
+        ).unwrap();
+        assert_eq!(first_seen, stored);
+    }
+
+    #[test]
+    fn test_update_player_summaries_pending_invite() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        let accepted = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        let invite = |friend_since| [Friend { steam_id: SteamId(1), relationship: Relationship::All, friend_since }];
+        let stored = |db: &DbConnection| db.get_current_friends().unwrap()[0].friend_since;
+
+        db.update_player_summaries(&mut invite(None), &mut [summary(1, "one")]).unwrap();
+        assert_eq!(None, stored(&db));
+        assert!(db.find_impossible_friendships().unwrap().is_empty());
+
+        // Once it's accepted it gets one, which then sticks like any other
+        db.update_player_summaries(&mut invite(Some(accepted)), &mut [summary(1, "one")]).unwrap();
+        assert_eq!(Some(accepted), stored(&db));
+        db.update_player_summaries(&mut invite(None), &mut [summary(1, "one")]).unwrap();
+        assert_eq!(Some(accepted), stored(&db));
+
+        // An accepted duplicate wins over a pending one, whichever comes first
+        let duplicate = |friend_since| Friend { steam_id: SteamId(2), relationship: Relationship::All, friend_since };
+        let mut friends = [duplicate(None), duplicate(Some(accepted)), duplicate(None)];
+        db.update_player_summaries(&mut friends, &mut [summary(1, "one"), summary(2, "two")]).unwrap();
+        assert_eq!(Some(accepted), db.get_current_friends().unwrap()[1].friend_since);
+    }
+}
@@ -1,33 +1,216 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::num::ParseIntError;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use chrono::{
     prelude::*,
-    serde::ts_seconds,
+    serde::{ts_seconds, ts_seconds_option},
 };
 use itertools::Itertools;
 use reqwest::{
     blocking::Client,
+    header::{CONTENT_LENGTH, RETRY_AFTER},
+    Proxy,
+    StatusCode,
     Url,
 };
 use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
-use serde::Deserialize;
+use serde::{de::{self, DeserializeOwned, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+use crate::graph::FriendGraph;
+use crate::metrics;
+use crate::transport::{CachingTransport, HttpTransport, RawResponse, Transport};
+
+/// Most IDs Steam accepts in a single `steamids` param.
+pub(crate) const MAX_IDS_PER_REQUEST: usize = 100;
+
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 50 * 1024 * 1024;
+
+/// Where every Web API endpoint lives, unless the client is pointed somewhere else.
+pub(crate) const DEFAULT_BASE_URL: &str = "https://api.steampowered.com/";
+
+/// So a stalled request fails instead of hanging a whole sync.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much of an error response's body to keep in [`SteamFailure::Http`].
+pub(crate) const MAX_ERROR_BODY_BYTES: usize = 4 * 1024;
 
 
 #[derive(Debug, thiserror::Error)]
 pub enum SteamFailure {
+    /// The API key is scrubbed from the error's URL, see the `From` impl.
     #[error("Error in HTTP request: {0}")]
-    Request(#[from] reqwest::Error),
+    Request(reqwest::Error),
     #[error("Error deserializing request: {0}")]
     Deserialize(#[from] serde_json::Error),
+    #[error("Steam responded with {status}: {body}")]
+    Http {
+        status: StatusCode,
+        /// The start of the response body, for debugging.
+        body: String,
+    },
+    #[error("Gave up after {attempts} attempts: {source}")]
+    RetriesExhausted {
+        attempts: u32,
+        source: Box<SteamFailure>,
+    },
+    #[error("Profile {0} is private")]
+    PrivateProfile(SteamId),
+    /// Steam answered with an error message in place of the data, usually with a 200.
+    #[error("Steam rejected the request: {0}")]
+    Api(String),
+    #[error("Steam returned no summary for {0}")]
+    NoSummary(SteamId),
+    #[error("Error reading response body: {0}")]
+    Body(std::io::Error),
+    #[error("Response was larger than the {limit} byte limit")]
+    ResponseTooLarge { limit: u64 },
+    #[error("Invalid avatar URL: {0}")]
+    InvalidAvatarUrl(String),
+    #[error("Invalid group URL: {0}")]
+    InvalidGroupUrl(String),
+    #[error("Invalid base URL: {0}")]
+    InvalidBaseUrl(String),
+    #[error("Error parsing XML: {0}")]
+    Xml(String),
+    #[error("Steam accepts at most 100 IDs per request, got {0}")]
+    TooManyIds(usize),
+    #[error("No root Steam ID was configured on the client")]
+    NoRootId,
+    #[error("No profile has the vanity URL {0:?}")]
+    VanityNotFound(String),
+    #[error("Proxy URL must use the socks5:// or socks5h:// scheme, got {0}")]
+    InvalidProxy(Url),
+    #[error("Error fetching chunk {chunk} of player summaries: {source}")]
+    SummaryChunk {
+        chunk: usize,
+        /// Summaries from the chunks before `chunk`, which did succeed.
+        fetched: Vec<PlayerSummary>,
+        source: Box<SteamFailure>,
+    },
 }
 
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize)]
-#[serde(try_from = "&str")]
-// 64-bit Steam IDs are a packed data structure, but for laziness' sake we'll leave it as an unvalidated number.
+// `reqwest` errors can include the request URL, which has the API key in it
+impl From<reqwest::Error> for SteamFailure {
+    fn from(mut e: reqwest::Error) -> Self {
+        if let Some(url) = e.url_mut() {
+            redact_api_key(url);
+        }
+        SteamFailure::Request(e)
+    }
+}
+
+/// Replaces the value of any `key` query param in `url`.
+fn redact_api_key(url: &mut Url) {
+    if !url.query_pairs().any(|(name, _)| name == "key") {
+        return;
+    }
+
+    let pairs = url.query_pairs()
+        .map(|(name, value)| {
+            let value = if name == "key" { "REDACTED".into() } else { value };
+            (name.into_owned(), value.into_owned())
+        })
+        .collect::<Vec<_>>();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+}
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidSteamId {
+    #[error("Not a number: {0}")]
+    Parse(#[from] ParseIntError),
+    #[error("Invalid universe {0}")]
+    Universe(u8),
+    #[error("Invalid account type {0}")]
+    AccountType(u8),
+}
+
+
+// 64-bit Steam IDs are a packed data structure, from the top: 8 bits of universe, 4 of account type,
+// 20 of instance, then the 32 bit account ID.
 // https://developer.valvesoftware.com/wiki/SteamID
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct SteamId(pub i64);
 
+impl SteamId {
+    /// The inner value is signed to match SQLite's `INTEGER`, this takes the ID as Steam writes it.
+    pub fn new(steam_id: u64) -> Self {
+        Self(steam_id as i64)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0 as u64
+    }
+
+    /// 1 is the public universe, which is where every regular account lives.
+    pub fn universe(&self) -> u8 {
+        (self.as_u64() >> 56) as u8
+    }
+
+    /// 1 is an individual (user) account, 7 a group. See the wiki link above for the rest.
+    pub fn account_type(&self) -> u8 {
+        ((self.as_u64() >> 52) & 0xF) as u8
+    }
+
+    /// 1 for user accounts, other types use it for flags.
+    pub fn instance(&self) -> u32 {
+        ((self.as_u64() >> 32) & 0xF_FFFF) as u32
+    }
+
+    /// The per-account part of the ID, i.e. its low 32 bits. Accounts are numbered in creation order.
+    pub fn account_id(&self) -> u32 {
+        (self.0 & 0xFFFF_FFFF) as u32
+    }
+
+    /// The classic `STEAM_X:Y:Z` format. The public universe is written as 0 like older games and most tools do.
+    pub fn to_steam_id2(&self) -> String {
+        let universe = match self.universe() {
+            1 => 0,
+            universe => universe,
+        };
+        format!("STEAM_{universe}:{}:{}", self.account_id() & 1, self.account_id() >> 1)
+    }
+
+    /// The `[U:1:W]` format, e.g. `[U:1:12345]` for a user or `[g:1:12345]` for a group.
+    pub fn to_steam_id3(&self) -> String {
+        let letter = match self.account_type() {
+            1 => 'U',
+            2 => 'M',
+            3 => 'G',
+            4 => 'A',
+            5 => 'P',
+            6 => 'C',
+            7 => 'g',
+            8 => 'T',
+            10 => 'a',
+            _ => 'I',
+        };
+        match letter {
+            // Anonymous game servers need the instance to be told apart
+            'A' => format!("[{letter}:{}:{}:{}]", self.universe(), self.account_id(), self.instance()),
+            _ => format!("[{letter}:{}:{}]", self.universe(), self.account_id()),
+        }
+    }
+
+    /// Roughly when this account was created, going by its account ID.
+    /// This is only an approximation: the bucket edges are eyeballed from public account-ID/creation-date pairs,
+    /// so accounts near an edge can easily land in the neighbouring era.
+    pub fn account_creation_era(&self) -> AccountEra {
+        match self.account_id() {
+            0..=15_000_000 => AccountEra::Early2003To2005,
+            15_000_001..=60_000_000 => AccountEra::Y2006To2010,
+            60_000_001..=160_000_000 => AccountEra::Y2011To2013,
+            160_000_001..=380_000_000 => AccountEra::Y2014To2016,
+            380_000_001..=1_000_000_000 => AccountEra::Y2017To2019,
+            _ => AccountEra::Y2020Onwards,
+        }
+    }
+}
+
 impl ToSql for SteamId {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         Ok(ToSqlOutput::Owned(self.0.into()))
@@ -46,38 +229,263 @@ impl Display for SteamId {
     }
 }
 
+// Steam sends 64-bit IDs as strings, so we do the same. It also keeps them intact in JS-based tooling.
+impl Serialize for SteamId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Rejects IDs from universes or of account types that don't exist.
 impl TryFrom<&str> for SteamId {
+    type Error = InvalidSteamId;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let steam_id = SteamId(value.parse()?);
+        if !(1..=4).contains(&steam_id.universe()) {
+            return Err(InvalidSteamId::Universe(steam_id.universe()));
+        }
+        if !(1..=10).contains(&steam_id.account_type()) {
+            return Err(InvalidSteamId::AccountType(steam_id.account_type()));
+        }
+        Ok(steam_id)
+    }
+}
+
+// Whatever Steam itself sends is taken as-is, so this only checks that it's a number. Most endpoints send it as a
+// string, but some (and some mirrors) send a plain JSON number.
+impl<'de> Deserialize<'de> for SteamId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SteamIdVisitor;
+
+        impl Visitor<'_> for SteamIdVisitor {
+            type Value = SteamId;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a 64-bit Steam ID as a string or number")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<SteamId, E> {
+                value.parse().map(SteamId).map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<SteamId, E> {
+                Ok(SteamId::new(value))
+            }
+        }
+
+        deserializer.deserialize_any(SteamIdVisitor)
+    }
+}
+
+
+/// Approximate account creation period, see [`SteamId::account_creation_era`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum AccountEra {
+    Early2003To2005,
+    Y2006To2010,
+    Y2011To2013,
+    Y2014To2016,
+    Y2017To2019,
+    Y2020Onwards,
+}
+
+impl AccountEra {
+    /// First year an account from this era could have been created in.
+    pub fn earliest_year(&self) -> i32 {
+        match self {
+            AccountEra::Early2003To2005 => 2003,
+            AccountEra::Y2006To2010 => 2006,
+            AccountEra::Y2011To2013 => 2011,
+            AccountEra::Y2014To2016 => 2014,
+            AccountEra::Y2017To2019 => 2017,
+            AccountEra::Y2020Onwards => 2020,
+        }
+    }
+}
+
+
+// Kept distinct from plain integers so an app ID can't be mixed up with a level, count, etc.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct AppId(pub u32);
+
+impl ToSql for AppId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(self.0.into()))
+    }
+}
+
+impl FromSql for AppId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(Self(u32::column_result(value)?))
+    }
+}
+
+impl Display for AppId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<u32> for AppId {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+
+/// Time spent in a game, in minutes, which is how Steam reports it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize)]
+#[serde(transparent)]
+pub struct Playtime(pub u32);
+
+impl Playtime {
+    pub fn hours(&self) -> f64 {
+        f64::from(self.0) / 60.0
+    }
+}
+
+impl ToSql for Playtime {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(self.0.into()))
+    }
+}
+
+impl FromSql for Playtime {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(Self(u32::column_result(value)?))
+    }
+}
+
+
+// https://partner.steamgames.com/doc/webapi/ISteamUser#GetUserGroupList
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize)]
+#[serde(try_from = "&str")]
+pub struct GroupId(pub u64);
+
+impl Display for GroupId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<&str> for GroupId {
     type Error = ParseIntError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        Ok(SteamId(value.parse()?))
+        Ok(GroupId(value.parse()?))
     }
 }
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Relationship {
     All,
     Friend,
 }
 
+impl Relationship {
+    /// As Steam spells it in the `relationship` param.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Relationship::All => "all",
+            Relationship::Friend => "friend",
+        }
+    }
+}
+
+// Stored the same way Steam spells it
+impl ToSql for Relationship {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
 
 // https://developer.valvesoftware.com/wiki/Steam_Web_API#GetFriendList_.28v0001.29
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Friend {
     #[serde(rename = "steamid")]
     pub steam_id: SteamId,
-    #[allow(dead_code)]
     pub relationship: Relationship,
-    #[serde(with = "ts_seconds")]
-    pub friend_since: DateTime<Utc>,
+    /// `None` for pending invites, which Steam sends with a `friend_since` of 0.
+    #[serde(default, deserialize_with = "deserialize_nonzero_timestamp", serialize_with = "ts_seconds_option::serialize")]
+    pub friend_since: Option<DateTime<Utc>>,
+}
+
+
+/// Steam's `personastate`. Private profiles always show as [`PersonaState::Offline`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "u8", into = "u8")]
+pub enum PersonaState {
+    #[default]
+    Offline,
+    Online,
+    Busy,
+    Away,
+    Snooze,
+    LookingToTrade,
+    LookingToPlay,
+    /// Anything Steam adds later.
+    Other(u8),
+}
+
+impl PersonaState {
+    /// Anything but offline, away and snooze included.
+    pub fn is_online(self) -> bool {
+        self != PersonaState::Offline
+    }
+}
+
+impl From<u8> for PersonaState {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PersonaState::Offline,
+            1 => PersonaState::Online,
+            2 => PersonaState::Busy,
+            3 => PersonaState::Away,
+            4 => PersonaState::Snooze,
+            5 => PersonaState::LookingToTrade,
+            6 => PersonaState::LookingToPlay,
+            other => PersonaState::Other(other),
+        }
+    }
+}
+
+impl From<PersonaState> for u8 {
+    fn from(value: PersonaState) -> Self {
+        match value {
+            PersonaState::Offline => 0,
+            PersonaState::Online => 1,
+            PersonaState::Busy => 2,
+            PersonaState::Away => 3,
+            PersonaState::Snooze => 4,
+            PersonaState::LookingToTrade => 5,
+            PersonaState::LookingToPlay => 6,
+            PersonaState::Other(other) => other,
+        }
+    }
+}
+
+impl ToSql for PersonaState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(u8::from(*self).into()))
+    }
+}
+
+impl FromSql for PersonaState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(u8::column_result(value)?.into())
+    }
 }
 
 
 // There's a lot more than this available, but this is enough for our purposes.
 // https://developer.valvesoftware.com/wiki/Steam_Web_API#GetPlayerSummaries_.28v0002.29
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+// Serializes back to the same shape Steam sends, so saved summaries can be read again
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PlayerSummary {
     #[serde(rename = "steamid")]
     pub steam_id: SteamId,
@@ -85,72 +493,2245 @@ pub struct PlayerSummary {
     pub persona_name: String,
     #[serde(rename = "profileurl")]
     pub profile_url: String,
+    /// URL of their 32x32 avatar.
+    #[serde(default)]
+    pub avatar: String,
+    /// URL of their 64x64 avatar.
+    #[serde(rename = "avatarmedium", default)]
+    pub avatar_medium: String,
+    /// URL of their 184x184 avatar.
+    #[serde(rename = "avatarfull", default)]
+    pub avatar_full: String,
+    #[serde(rename = "personastate", default)]
+    pub persona_state: PersonaState,
+    /// When they were last online. Only there for public profiles.
+    #[serde(rename = "lastlogoff", default, with = "ts_seconds_option")]
+    pub last_logoff: Option<DateTime<Utc>>,
+    /// The game they're in right now, if any.
+    #[serde(rename = "gameid", default, deserialize_with = "deserialize_game_id", serialize_with = "serialize_game_id")]
+    pub game_id: Option<AppId>,
+    /// The name of the game they're in, including non-Steam games.
+    #[serde(rename = "gameextrainfo", default, skip_serializing_if = "Option::is_none")]
+    pub game_extra_info: Option<String>,
+    /// The whole summary exactly as Steam sent it, fields we don't know about included.
+    #[serde(skip)]
+    pub raw_json: Option<String>,
+}
+
+/// `gameid` comes as a string, and for non-Steam games it's a 64-bit shortcut ID rather than an app ID,
+/// which we treat the same as not being in a game.
+fn deserialize_game_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<AppId>, D::Error> {
+    let game_id = Option::<String>::deserialize(deserializer)?;
+    Ok(game_id.and_then(|id| id.parse().ok()).map(AppId))
 }
 
+fn serialize_game_id<S: Serializer>(game_id: &Option<AppId>, serializer: S) -> Result<S::Ok, S::Error> {
+    game_id.map(|id| id.to_string()).serialize(serializer)
+}
 
-pub struct SteamClient<'a> {
-    api_key: &'a str,
-    client: Client,
+/// Parses a `GetFriendList` response body, without any of the status handling around it.
+pub fn parse_friend_list(body: &[u8]) -> Result<Vec<Friend>, SteamFailure> {
+    // We only need the structs to unwrap the "outer" parts of the resulting JSON, put them here
+    // to keep the top-level clear
+    #[derive(Debug, Deserialize)]
+    struct FriendsList {
+        // Steam sometimes leaves this out entirely for users with no friends
+        #[serde(default)]
+        friends: Vec<Friend>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Response {
+        #[serde(rename = "friendslist")]
+        friends_list: FriendsList,
+    }
+
+    let res: Response = parse_response(body)?;
+    Ok(res.friends_list.friends)
 }
 
-impl<'a> SteamClient<'a> {
+/// The error bodies Steam sends instead of the expected shape, e.g. `{"error":"..."}` or
+/// `{"response":{"success":2,"message":"..."}}`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ErrorEnvelope {
+    Error { error: String },
+    Nested { response: NestedError },
+}
 
-    pub fn new(api_key: &'a str) -> Self {
-        Self {
-            api_key,
-            // We know this can only be invalid if the programmer messes it up, so `expect` is fine
-            client: Client::builder()
-                .user_agent("steam-web-api-consumer/0.1 (cjblake97@gmail.com)")
-                .build()
-                .expect("User-Agent on client was invalid")
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum NestedError {
+    Error { error: String },
+    Message { message: String },
+}
+
+impl From<ErrorEnvelope> for SteamFailure {
+    fn from(envelope: ErrorEnvelope) -> Self {
+        match envelope {
+            ErrorEnvelope::Error { error }
+            | ErrorEnvelope::Nested { response: NestedError::Error { error } | NestedError::Message { message: error } } => {
+                SteamFailure::Api(error)
+            },
         }
     }
+}
 
-    pub fn get_friend_list(&self, steam_id: &str) -> Result<Vec<Friend>, SteamFailure> {
-        // We only need the structs to unwrap the "outer" parts of the resulting JSON, put them here
-        // to keep the top-level clear
-        #[derive(Debug, Deserialize)]
-        struct FriendsList {
-            friends: Vec<Friend>,
+/// [`SteamFailure::PrivateProfile`] for `steam_id`, or `otherwise` when it isn't even a number, since then there's no
+/// profile Steam could have looked up.
+pub(crate) fn private_profile(steam_id: &str, otherwise: SteamFailure) -> SteamFailure {
+    match steam_id.parse() {
+        Ok(steam_id) => SteamFailure::PrivateProfile(SteamId(steam_id)),
+        Err(_) => otherwise,
+    }
+}
+
+/// `body` as a `T`, or if it isn't one, whatever error Steam sent instead of it.
+/// Anything else keeps the error from deserializing `T`, which says more than the envelope's would.
+fn parse_response<T: DeserializeOwned>(body: &[u8]) -> Result<T, SteamFailure> {
+    let err = match serde_json::from_slice(body) {
+        Ok(res) => return Ok(res),
+        Err(err) => err,
+    };
+    // One name with invalid UTF-8 or a lone surrogate would otherwise fail the whole response
+    if let Some(body) = replace_invalid_text(body) {
+        if let Ok(res) = serde_json::from_slice(&body) {
+            return Ok(res);
         }
+    }
+    match serde_json::from_slice::<ErrorEnvelope>(body) {
+        Ok(envelope) => Err(envelope.into()),
+        Err(_) => Err(err.into()),
+    }
+}
 
-        #[derive(Debug, Deserialize)]
-        struct Response {
-            #[serde(rename = "friendslist")]
-            friends_list: FriendsList,
+/// Buckets everyone who's currently in a game by that game. Anyone not in a game is left out.
+pub fn group_by_current_game(summaries: &[PlayerSummary]) -> HashMap<AppId, Vec<SteamId>> {
+    let mut games: HashMap<AppId, Vec<SteamId>> = HashMap::new();
+    for summary in summaries {
+        if let Some(game_id) = summary.game_id {
+            games.entry(game_id).or_default().push(summary.steam_id);
         }
+    }
+    games
+}
+
+
+// https://developer.valvesoftware.com/wiki/Steam_Web_API#GetRecentlyPlayedGames_.28v0001.29
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RecentGame {
+    #[serde(rename = "appid")]
+    pub app_id: AppId,
+    #[serde(default)]
+    pub name: String,
+    pub playtime_2weeks: Playtime,
+    pub playtime_forever: Playtime,
+}
+
+
+// https://partner.steamgames.com/doc/webapi/ISteamUser#GetPlayerBans
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayerBan {
+    pub steam_id: SteamId,
+    #[serde(rename = "VACBanned")]
+    pub vac_banned: bool,
+    #[serde(rename = "NumberOfVACBans")]
+    pub number_of_vac_bans: u32,
+    #[serde(default)]
+    pub number_of_game_bans: u32,
+    /// Since the most recent VAC or game ban, 0 if there's never been one.
+    pub days_since_last_ban: u32,
+    pub community_banned: bool,
+    pub economy_ban: EconomyBan,
+}
+
+/// Trade ban status.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EconomyBan {
+    None,
+    Probation,
+    Banned,
+    #[serde(other)]
+    Unknown,
+}
+
+
+// https://developer.valvesoftware.com/wiki/Steam_Web_API#GetOwnedGames_.28v0001.29
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct OwnedGame {
+    #[serde(rename = "appid")]
+    pub app_id: AppId,
+    #[serde(default)]
+    pub name: String,
+    pub playtime_forever: Playtime,
+    /// Left out by Steam if they haven't played it in the last two weeks.
+    pub playtime_2weeks: Option<Playtime>,
+}
+
+
+// https://partner.steamgames.com/doc/webapi/ISteamUserStats#GetPlayerAchievements
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Achievement {
+    #[serde(rename = "apiname")]
+    pub api_name: String,
+    #[serde(deserialize_with = "deserialize_int_bool")]
+    pub achieved: bool,
+    /// `None` if it hasn't been unlocked, which Steam sends as 0.
+    #[serde(rename = "unlocktime", deserialize_with = "deserialize_nonzero_timestamp")]
+    pub unlock_time: Option<DateTime<Utc>>,
+}
 
-        let url = Url::parse_with_params(
-            "https://api.steampowered.com/ISteamUser/GetFriendList/v0001",
-            &[("key", self.api_key), ("steamid", steam_id)],
-        ).expect("Given an invalid URL");
-        let res: Response = serde_json::from_slice(self.client.get(url).send()?.bytes()?.as_ref())?;
 
-        Ok(res.friends_list.friends)
+// https://partner.steamgames.com/doc/webapi/IPlayerService#GetBadges
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Badge {
+    #[serde(rename = "badgeid")]
+    pub badge_id: u32,
+    pub level: u32,
+    #[serde(with = "ts_seconds")]
+    pub completion_time: DateTime<Utc>,
+    pub xp: u32,
+    /// How many people have this badge.
+    pub scarcity: u32,
+}
+
+fn deserialize_int_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(u8::deserialize(deserializer)? != 0)
+}
+
+/// Steam uses 0 for "never" in timestamps like `unlocktime` and `friend_since`. `null` is taken to mean the same, so
+/// what we serialize reads back in.
+fn deserialize_nonzero_timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let timestamp = ts_seconds_option::deserialize(deserializer)?;
+    Ok(timestamp.filter(|t| t.timestamp() != 0))
+}
+
+
+// https://developer.valvesoftware.com/wiki/Steam_Web_API#GetAppList_.28v0002.29
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct App {
+    #[serde(rename = "appid")]
+    pub app_id: AppId,
+    pub name: String,
+}
+
+
+/// An operation to estimate the request cost of with [`SteamClient::estimate_requests`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedOp {
+    /// Player summaries for this many IDs.
+    Summaries { ids: usize },
+    /// One friend list per user.
+    FriendLists { users: usize },
+    /// One owned-games list per user.
+    OwnedGames { users: usize },
+    /// A full sync: the friend list, then summaries for everyone on it.
+    Sync { friends: usize },
+    /// Crawling friend lists out to `depth` hops from a root, assuming everyone has `avg_friends` friends.
+    /// Depth 1 is just the root's list, depth 2 adds each of their friends' lists, and so on.
+    FriendNetwork { depth: u8, avg_friends: usize },
+}
+
+
+/// Retries for requests that fail with a 429 or 5xx, see [`SteamClientBuilder::retry_policy`].
+/// The delay doubles after each attempt, unless Steam says how long to wait with `Retry-After`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Including the first one, so 1 means no retries.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        Self { max_attempts: max_attempts.max(1), base_delay }
     }
 
-    pub fn get_player_summaries(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
-        #[derive(Debug, Deserialize)]
-        struct Players {
-            players: Vec<PlayerSummary>,
-        }
+    /// How long to wait after failed attempt number `attempt`, counting from 1.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(attempt - 1))
+    }
+}
 
-        #[derive(Debug, Deserialize)]
-        struct Response {
-            response: Players,
-        }
+impl Default for RetryPolicy {
+    /// No retries.
+    fn default() -> Self {
+        Self::new(1, Duration::ZERO)
+    }
+}
 
-        let mut ret = Vec::with_capacity(steam_ids.len());
-        for chunk in steam_ids.chunks(100) {
-            let url = Url::parse_with_params(
-                "https://api.steampowered.com/ISteamUser/GetPlayerSummaries/v0002",
-                &[("key", self.api_key), ("steamids", &chunk.iter().join(","))],
-            ).expect("Given an invalid const URL");
 
-            let mut res: Response = serde_json::from_slice(self.client.get(url).send()?.bytes()?.as_ref())?;
-            ret.append(&mut res.response.players);
-        }
+pub struct SteamClient<'a> {
+    api_key: &'a str,
+    root_id: Option<SteamId>,
+    language: Option<String>,
+    max_response_bytes: u64,
+    timeout: Option<Duration>,
+    app_list_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    base_url: Url,
+    transport: Box<dyn Transport>,
+    requests_made: AtomicU64,
+}
 
-        Ok(ret)
+pub struct SteamClientBuilder<'a> {
+    api_key: &'a str,
+    root_id: Option<SteamId>,
+    language: Option<String>,
+    max_response_bytes: u64,
+    timeout: Option<Duration>,
+    app_list_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    base_url: Url,
+    socks5_proxy: Option<Url>,
+    client: Option<Client>,
+    transport: Option<Box<dyn Transport>>,
+}
+
+impl<'a> SteamClientBuilder<'a> {
+    /// The account this client acts on behalf of, used by methods like [`SteamClient::my_summary`].
+    pub fn root_id(mut self, steam_id: SteamId) -> Self {
+        self.root_id = Some(steam_id);
+        self
+    }
+
+    /// Language to ask Steam for (e.g. `"english"`, `"german"`) on endpoints that honor it.
+    /// None of the fields in [`PlayerSummary`] are localized, but it's still sent so output is consistent once
+    /// localized endpoints are in the mix.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    /// Largest response body the client will accept before failing with [`SteamFailure::ResponseTooLarge`].
+    /// Defaults to 50 MB, which leaves plenty of room for big responses like the full app list.
+    pub fn max_response_bytes(mut self, max_response_bytes: u64) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Timeout for every request, unless an endpoint has its own. Defaults to 30 seconds, or whatever the
+    /// [`Self::client`] was set up with.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for [`SteamClient::get_app_list`] only, which can take far longer than everything else.
+    pub fn timeout_for_app_list(mut self, timeout: Duration) -> Self {
+        self.app_list_timeout = Some(timeout);
+        self
+    }
+
+    /// How to retry requests that fail with a 429 or 5xx. By default they aren't.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sends Web API requests to `base_url` instead of `https://api.steampowered.com/`, e.g. a local mock server.
+    /// Community site requests like [`SteamClient::get_group_members`] aren't affected.
+    pub fn base_url(mut self, base_url: Url) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Routes every request through a SOCKS5 proxy, e.g. `socks5://127.0.0.1:1080`.
+    /// Use the `socks5h://` scheme to have the proxy resolve DNS as well.
+    pub fn socks5_proxy(mut self, proxy: Url) -> Self {
+        self.socks5_proxy = Some(proxy);
+        self
+    }
+
+    /// Sends requests with an existing `reqwest` client instead of making a new one, e.g. to share its connection pool
+    /// between clients with different API keys, or to configure it beyond what this builder offers. The proxy is
+    /// ignored when this is set.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sends requests through `transport` instead of a `reqwest` client. HTTP-level options like the
+    /// proxy are ignored when this is set.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Box::new(transport));
+        self
+    }
+
+    pub fn build(self) -> Result<SteamClient<'a>, SteamFailure> {
+        let base_url = checked_base_url(self.base_url)?;
+        // An injected client may have a timeout of its own, which ours would override
+        let timeout = match (self.timeout, &self.client) {
+            (Some(timeout), _) => Some(timeout),
+            (None, Some(_)) => None,
+            (None, None) => Some(DEFAULT_TIMEOUT),
+        };
+        let transport: Box<dyn Transport> = match (self.transport, self.client) {
+            (Some(transport), _) => transport,
+            (None, Some(client)) => Box::new(HttpTransport::new(client, self.max_response_bytes)),
+            (None, None) => {
+                let mut builder = Client::builder()
+                    .user_agent("steam-web-api-consumer/0.1 (cjblake97@gmail.com)");
+
+                if let Some(proxy) = self.socks5_proxy {
+                    if !matches!(proxy.scheme(), "socks5" | "socks5h") {
+                        return Err(SteamFailure::InvalidProxy(proxy));
+                    }
+                    builder = builder.proxy(Proxy::all(proxy)?);
+                }
+
+                Box::new(HttpTransport::new(builder.build()?, self.max_response_bytes))
+            },
+        };
+
+        Ok(SteamClient {
+            api_key: self.api_key,
+            root_id: self.root_id,
+            language: self.language,
+            max_response_bytes: self.max_response_bytes,
+            timeout,
+            app_list_timeout: self.app_list_timeout,
+            retry_policy: self.retry_policy,
+            base_url,
+            transport,
+            requests_made: AtomicU64::new(0),
+        })
     }
-}
\ No newline at end of file
+}
+
+/// The part of [`SteamClient`] that syncing and crawling need, so they can be tested against a fake instead.
+pub trait SteamApi {
+    fn get_friend_list(&self, steam_id: &str) -> Result<Vec<Friend>, SteamFailure>;
+
+    fn get_player_summaries(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure>;
+}
+
+impl SteamApi for SteamClient<'_> {
+    fn get_friend_list(&self, steam_id: &str) -> Result<Vec<Friend>, SteamFailure> {
+        SteamClient::get_friend_list(self, steam_id)
+    }
+
+    fn get_player_summaries(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        SteamClient::get_player_summaries(self, steam_ids)
+    }
+}
+
+// Written out to keep the API key out of logs
+impl std::fmt::Debug for SteamClient<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SteamClient")
+            .field("api_key", &"REDACTED")
+            .field("root_id", &self.root_id)
+            .field("language", &self.language)
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("timeout", &self.timeout)
+            .field("app_list_timeout", &self.app_list_timeout)
+            .field("retry_policy", &self.retry_policy)
+            .field("requests_made", &self.requests_made)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> SteamClient<'a> {
+
+    pub fn new(api_key: &'a str) -> Self {
+        // We know this can only be invalid if the programmer messes it up, so `expect` is fine
+        Self::builder(api_key)
+            .build()
+            .expect("User-Agent on client was invalid")
+    }
+
+    /// Same as [`Self::new`], but sends requests with `client`. See [`SteamClientBuilder::client`].
+    pub fn with_client(api_key: &'a str, client: Client) -> Self {
+        Self::builder(api_key)
+            .client(client)
+            .build()
+            .expect("Building with an existing client can't fail")
+    }
+
+    pub fn builder(api_key: &'a str) -> SteamClientBuilder<'a> {
+        SteamClientBuilder {
+            api_key,
+            root_id: None,
+            language: None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            timeout: None,
+            app_list_timeout: None,
+            retry_policy: RetryPolicy::default(),
+            base_url: Url::parse(DEFAULT_BASE_URL).expect("Given an invalid const URL"),
+            socks5_proxy: None,
+            client: None,
+            transport: None,
+        }
+    }
+
+    /// Same as [`SteamClientBuilder::timeout`], for a client that's already built.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Serves responses from `dir` when the same request was made less than `ttl` ago, see [`CachingTransport`].
+    /// Cached responses still count towards [`Self::requests_made`].
+    pub fn with_cache(mut self, dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        self.transport = Box::new(CachingTransport::new(self.transport, dir, ttl));
+        self
+    }
+
+    /// Same as [`SteamClientBuilder::base_url`], for a client that's already built.
+    pub fn with_base_url(mut self, base_url: &str) -> Result<Self, SteamFailure> {
+        let url = Url::parse(base_url).map_err(|_| SteamFailure::InvalidBaseUrl(base_url.to_string()))?;
+        self.base_url = checked_base_url(url)?;
+        Ok(self)
+    }
+
+    /// Roughly how many requests `op` will take, to check a big job against the API quota before starting it.
+    /// Retries aren't counted.
+    pub fn estimate_requests(op: &PlannedOp) -> usize {
+        match *op {
+            PlannedOp::Summaries { ids } => ids.div_ceil(MAX_IDS_PER_REQUEST),
+            PlannedOp::FriendLists { users } | PlannedOp::OwnedGames { users } => users,
+            PlannedOp::Sync { friends } => 1 + friends.div_ceil(MAX_IDS_PER_REQUEST),
+            PlannedOp::FriendNetwork { depth, avg_friends } => {
+                // 1 + F + F^2 + ... + F^(depth - 1) friend lists
+                (0..u32::from(depth))
+                    .map(|level| avg_friends.saturating_pow(level))
+                    .fold(0, usize::saturating_add)
+            },
+        }
+    }
+
+    /// Number of requests this client has sent, successful or not.
+    pub fn requests_made(&self) -> u64 {
+        self.requests_made.load(Ordering::Relaxed)
+    }
+
+    /// This client's counters in the Prometheus text format.
+    pub fn metrics_text(&self) -> String {
+        let mut out = String::new();
+        metrics::write_metric(&mut out, "steam_api_requests_total", "counter", "Requests sent to the Steam Web API", self.requests_made());
+        out
+    }
+
+    /// `endpoint` (e.g. `ISteamUser/GetFriendList/v0001`) under the base URL, with `params` as its query.
+    fn api_url(&self, endpoint: &str, params: &[(&str, &str)]) -> Url {
+        let mut url = self.base_url.join(endpoint).expect("Given an invalid endpoint");
+        if !params.is_empty() {
+            url.query_pairs_mut().extend_pairs(params);
+        }
+        url
+    }
+
+    fn get(&self, url: Url) -> Result<RawResponse, SteamFailure> {
+        self.get_with_timeout(url, self.timeout)
+    }
+
+    fn get_with_timeout(&self, url: Url, timeout: Option<Duration>) -> Result<RawResponse, SteamFailure> {
+        let mut attempt = 1;
+        loop {
+            self.requests_made.fetch_add(1, Ordering::Relaxed);
+            let res = self.transport.get(&url, timeout)?;
+            // Just the path, the query has the API key in it
+            tracing::debug!(endpoint = url.path(), status = res.status.as_u16(), attempt, "Steam responded");
+
+            // `HttpTransport` already stops reading at the limit, but other transports might not
+            let content_length = res.headers.get(CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse::<u64>().ok());
+            if content_length.is_some_and(|len| len > self.max_response_bytes) || res.body.len() as u64 > self.max_response_bytes {
+                return Err(SteamFailure::ResponseTooLarge { limit: self.max_response_bytes });
+            }
+            if res.status.is_success() {
+                return Ok(res);
+            }
+
+            let retryable = res.status == StatusCode::TOO_MANY_REQUESTS || res.status.is_server_error();
+            if retryable && attempt < self.retry_policy.max_attempts {
+                // Only the delay-seconds form of `Retry-After`, Steam doesn't send dates
+                let retry_after = res.headers.get(RETRY_AFTER)
+                    .and_then(|secs| secs.to_str().ok())
+                    .and_then(|secs| secs.parse().ok())
+                    .map(Duration::from_secs);
+                let delay = retry_after.unwrap_or_else(|| self.retry_policy.delay(attempt));
+                tracing::warn!(endpoint = url.path(), status = res.status.as_u16(), attempt, ?delay, "Retrying request");
+                std::thread::sleep(delay);
+                attempt += 1;
+                continue;
+            }
+
+            let body = &res.body[..res.body.len().min(MAX_ERROR_BODY_BYTES)];
+            let err = SteamFailure::Http { status: res.status, body: String::from_utf8_lossy(body).into_owned() };
+            return match attempt {
+                1 => Err(err),
+                attempts => Err(SteamFailure::RetriesExhausted { attempts, source: Box::new(err) }),
+            };
+        }
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, url: Url) -> Result<T, SteamFailure> {
+        parse_response(&self.get(url)?.body)
+    }
+
+    /// Like [`Self::get`], for endpoints about `steam_id` that Steam refuses outright when their profile is private.
+    fn get_profile(&self, url: Url, steam_id: &str) -> Result<RawResponse, SteamFailure> {
+        match self.get(url) {
+            Err(err @ SteamFailure::Http { status, .. }) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+                Err(private_profile(steam_id, err))
+            },
+            res => res,
+        }
+    }
+
+    /// Like [`Self::get_json`], see [`Self::get_profile`].
+    fn get_profile_json<T: DeserializeOwned>(&self, url: Url, steam_id: &str) -> Result<T, SteamFailure> {
+        match parse_response(&self.get_profile(url, steam_id)?.body) {
+            Err(SteamFailure::Api(message)) if message.to_lowercase().contains("private") => {
+                Err(private_profile(steam_id, SteamFailure::Api(message)))
+            },
+            res => res,
+        }
+    }
+
+    pub fn get_friend_list(&self, steam_id: &str) -> Result<Vec<Friend>, SteamFailure> {
+        self.get_friend_list_with_relationship(steam_id, Relationship::Friend)
+    }
+
+    /// Same as [`Self::get_friend_list`], but [`Relationship::All`] also includes pending invites.
+    pub fn get_friend_list_with_relationship(&self, steam_id: &str, relationship: Relationship) -> Result<Vec<Friend>, SteamFailure> {
+        // Steam refuses to show private friend lists rather than returning an empty one
+        parse_friend_list(&self.get_profile(self.friend_list_url(steam_id, relationship), steam_id)?.body)
+    }
+
+    /// [`Self::get_friend_list`]'s response as plain JSON, to see what Steam actually sent when it won't deserialize.
+    pub fn get_friend_list_json(&self, steam_id: &str) -> Result<serde_json::Value, SteamFailure> {
+        self.get_profile_json(self.friend_list_url(steam_id, Relationship::Friend), steam_id)
+    }
+
+    fn friend_list_url(&self, steam_id: &str, relationship: Relationship) -> Url {
+        self.api_url(
+            "ISteamUser/GetFriendList/v0001",
+            &[("key", self.api_key), ("steamid", steam_id), ("relationship", relationship.as_str())],
+        )
+    }
+
+    /// Looks up the Steam ID behind a vanity URL name, e.g. `gabelogannewell` for
+    /// `https://steamcommunity.com/id/gabelogannewell`.
+    pub fn resolve_vanity_url(&self, vanity: &str) -> Result<SteamId, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Resolved {
+            /// 1 if found, 42 if not
+            success: u8,
+            steamid: Option<SteamId>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Resolved,
+        }
+
+        let url = self.api_url(
+            "ISteamUser/ResolveVanityURL/v0001",
+            &[("key", self.api_key), ("vanityurl", vanity)],
+        );
+        let res: Response = self.get_json(url)?;
+
+        match res.response {
+            Resolved { success: 1, steamid: Some(steam_id) } => Ok(steam_id),
+            _ => Err(SteamFailure::VanityNotFound(vanity.to_string())),
+        }
+    }
+
+    /// `input` as a Steam ID if it is one, otherwise resolved as a vanity URL name. Full profile URLs like
+    /// `https://steamcommunity.com/id/gabelogannewell` or `steamcommunity.com/profiles/76561197960287930` work too.
+    pub fn resolve(&self, input: &str) -> Result<SteamId, SteamFailure> {
+        let input = input.trim().trim_end_matches('/');
+        let (kind, name) = input.rsplit_once('/')
+            .map(|(rest, name)| (rest.rsplit('/').next().unwrap_or_default(), name))
+            .unwrap_or(("", input));
+
+        match SteamId::try_from(name) {
+            Ok(steam_id) if kind != "id" => Ok(steam_id),
+            _ => self.resolve_vanity_url(name),
+        }
+    }
+
+    /// Games in `steam_id`'s library, free games they've played included. Empty if their game details are private.
+    pub fn get_owned_games(&self, steam_id: &str) -> Result<Vec<OwnedGame>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Games {
+            // Left out entirely when game details are private
+            #[serde(default)]
+            games: Vec<OwnedGame>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Games,
+        }
+
+        let url = self.api_url(
+            "IPlayerService/GetOwnedGames/v0001",
+            &[
+                ("key", self.api_key),
+                ("steamid", steam_id),
+                ("include_appinfo", "1"),
+                ("include_played_free_games", "1"),
+            ],
+        );
+        let res: Response = self.get_json(url)?;
+
+        Ok(res.response.games)
+    }
+
+    /// `steam_id`'s Steam community level, or `None` if their profile is private.
+    /// See [`crate::level::SteamLevel`] for the XP that takes.
+    pub fn get_steam_level(&self, steam_id: &str) -> Result<Option<u32>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Level {
+            // Private profiles get an empty `response`
+            player_level: Option<u32>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Level,
+        }
+
+        let url = self.api_url(
+            "IPlayerService/GetSteamLevel/v1",
+            &[("key", self.api_key), ("steamid", steam_id)],
+        );
+        let res: Response = self.get_json(url)?;
+
+        Ok(res.response.player_level)
+    }
+
+    /// Every badge `steam_id` has earned. Empty if their profile is private.
+    /// Their total XP and level come along in the same response, see [`Self::get_steam_level`] for the latter.
+    pub fn get_badges(&self, steam_id: &str) -> Result<Vec<Badge>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Badges {
+            // Private profiles get an empty `response`
+            #[serde(default)]
+            badges: Vec<Badge>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Badges,
+        }
+
+        let url = self.api_url("IPlayerService/GetBadges/v1", &[("key", self.api_key), ("steamid", steam_id)]);
+        match self.get_profile_json::<Response>(url, steam_id) {
+            Ok(res) => Ok(res.response.badges),
+            Err(SteamFailure::PrivateProfile(_)) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every achievement in `app_id` and whether `steam_id` has it.
+    /// Empty if the game has no achievements, or `steam_id`'s game details are private.
+    pub fn get_player_achievements(&self, steam_id: &str, app_id: AppId) -> Result<Vec<Achievement>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct PlayerStats {
+            success: bool,
+            #[serde(default)]
+            achievements: Vec<Achievement>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            #[serde(rename = "playerstats")]
+            player_stats: PlayerStats,
+        }
+
+        let app_id = app_id.to_string();
+        let url = self.api_url(
+            "ISteamUserStats/GetPlayerAchievements/v0001",
+            &[("key", self.api_key), ("steamid", steam_id), ("appid", app_id.as_str())],
+        );
+
+        let res: Response = match self.get(url) {
+            Ok(res) => parse_response(&res.body)?,
+            // Both of those come back as a 400 or 403 with `success: false`, anything else is a real error
+            Err(SteamFailure::Http { status, body }) if status == StatusCode::BAD_REQUEST || status == StatusCode::FORBIDDEN => {
+                match serde_json::from_str(&body) {
+                    Ok(res) => res,
+                    Err(_) => return Err(SteamFailure::Http { status, body }),
+                }
+            },
+            Err(e) => return Err(e),
+        };
+
+        if !res.player_stats.success {
+            return Ok(Vec::new());
+        }
+        Ok(res.player_stats.achievements)
+    }
+
+    /// Games `steam_id` played in the last two weeks. Empty if their game details are private.
+    pub fn get_recently_played_games(&self, steam_id: &str) -> Result<Vec<RecentGame>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Games {
+            // Left out entirely for private profiles, or if nothing was played
+            #[serde(default)]
+            games: Vec<RecentGame>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Games,
+        }
+
+        let url = self.api_url(
+            "IPlayerService/GetRecentlyPlayedGames/v0001",
+            &[("key", self.api_key), ("steamid", steam_id)],
+        );
+        let res: Response = self.get_json(url)?;
+
+        Ok(res.response.games)
+    }
+
+    /// Checks whether `me` is still on `friend`'s friend list. Steam can keep a stale entry on one side after
+    /// the other removed it, so being on my list doesn't guarantee I'm on theirs.
+    /// Returns `None` if `friend`'s friend list is private and it can't be determined.
+    pub fn check_mutual(&self, me: &SteamId, friend: &SteamId) -> Result<Option<bool>, SteamFailure> {
+        match self.get_friend_list(&friend.to_string()) {
+            Ok(friends) => Ok(Some(friends.iter().any(|f| f.steam_id == *me))),
+            Err(SteamFailure::PrivateProfile(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fewest friend hops from `from` to `to`, searching breadth-first out to `max_depth` hops. Returns `None` if `to`
+    /// isn't reachable that way. Private friend lists are dead ends.
+    /// Each hop costs a request per user at the previous depth, so keep `max_depth` small.
+    pub fn social_distance(&self, from: &SteamId, to: &SteamId, max_depth: u8) -> Result<Option<u8>, SteamFailure> {
+        if from == to {
+            return Ok(Some(0));
+        }
+
+        let mut seen = HashSet::from([*from]);
+        let mut frontier = vec![*from];
+        for depth in 1..=max_depth {
+            let mut next = Vec::new();
+            for steam_id in frontier {
+                let friends = match self.get_friend_list(&steam_id.to_string()) {
+                    Ok(friends) => friends,
+                    Err(SteamFailure::PrivateProfile(_)) => continue,
+                    Err(e) => return Err(e),
+                };
+                for friend in friends {
+                    if friend.steam_id == *to {
+                        return Ok(Some(depth));
+                    }
+                    if seen.insert(friend.steam_id) {
+                        next.push(friend.steam_id);
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        Ok(None)
+    }
+
+    /// Crawls friend lists out from `root`, see [`crate::graph::crawl`].
+    /// See [`PlannedOp::FriendNetwork`] for how quickly this adds up.
+    pub fn get_friend_graph(&self, root: &SteamId, depth: u8, max_lists: usize) -> Result<FriendGraph, SteamFailure> {
+        crate::graph::crawl(self, root, depth, max_lists)
+    }
+
+    pub fn get_user_groups(&self, steam_id: &str) -> Result<Vec<GroupId>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Group {
+            gid: GroupId,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Groups {
+            success: bool,
+            #[serde(default)]
+            groups: Vec<Group>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Groups,
+        }
+
+        let url = self.api_url(
+            "ISteamUser/GetUserGroupList/v1",
+            &[("key", self.api_key), ("steamid", steam_id)],
+        );
+        let res: Response = self.get_profile_json(url, steam_id)?;
+        // Private profiles sometimes come back as a 200 with `success: false` instead
+        if !res.response.success {
+            return Err(private_profile(steam_id, SteamFailure::Api("success: false".to_string())));
+        }
+
+        Ok(res.response.groups.into_iter().map(|g| g.gid).collect())
+    }
+
+    /// Groups both `a` and `b` are members of, in ascending order. A private profile counts as being in no groups.
+    pub fn shared_groups(&self, a: &SteamId, b: &SteamId) -> Result<Vec<GroupId>, SteamFailure> {
+        let groups_of = |steam_id: &SteamId| match self.get_user_groups(&steam_id.to_string()) {
+            Err(SteamFailure::PrivateProfile(_)) => Ok(Vec::new()),
+            res => res,
+        };
+
+        let a_groups = groups_of(a)?.into_iter().collect::<HashSet<_>>();
+        let mut shared = groups_of(b)?.into_iter()
+            .filter(|g| a_groups.contains(g))
+            .collect::<Vec<_>>();
+        shared.sort_unstable();
+        shared.dedup();
+
+        Ok(shared)
+    }
+
+    /// Every member of the group at `group_url` (e.g. `https://steamcommunity.com/groups/valve`), across all pages.
+    /// This uses the community site's XML member list rather than the Web API, which has no equivalent.
+    pub fn get_group_members(&self, group_url: &str) -> Result<Vec<SteamId>, SteamFailure> {
+        let mut url = Url::parse_with_params(
+            &format!("{}/memberslistxml/", group_url.trim_end_matches('/')),
+            &[("xml", "1")],
+        ).map_err(|_| SteamFailure::InvalidGroupUrl(group_url.to_string()))?;
+
+        let mut members = Vec::new();
+        for page in 1.. {
+            let res = self.get(url)?;
+            let xml = String::from_utf8_lossy(&res.body);
+
+            for id in xml_values(&xml, "steamID64") {
+                members.push(SteamId::try_from(id).map_err(|e| SteamFailure::Xml(format!("Bad steamID64 {id:?}: {e}")))?);
+            }
+
+            let total_pages = xml_values(&xml, "totalPages")
+                .first()
+                .and_then(|pages| pages.parse::<u32>().ok())
+                .unwrap_or(1);
+            // `totalPages` keeps us from looping forever if Steam ever links a page back to itself
+            match xml_values(&xml, "nextPageLink").first() {
+                Some(next) if page < total_pages => {
+                    url = Url::parse(next).map_err(|_| SteamFailure::Xml(format!("Bad nextPageLink {next:?}")))?;
+                },
+                _ => break,
+            }
+        }
+
+        Ok(members)
+    }
+
+    /// Every app on Steam. This is a big response, see [`SteamClientBuilder::timeout_for_app_list`].
+    pub fn get_app_list(&self) -> Result<Vec<App>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct AppList {
+            apps: Vec<App>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            #[serde(rename = "applist")]
+            app_list: AppList,
+        }
+
+        let url = self.api_url("ISteamApps/GetAppList/v2/", &[]);
+        let res = self.get_with_timeout(url, self.app_list_timeout.or(self.timeout))?;
+        let res: Response = parse_response(&res.body)?;
+
+        Ok(res.app_list.apps)
+    }
+
+    /// Fetches in chunks of 100 IDs. Fails with [`SteamFailure::SummaryChunk`] only when a chunk after the first one
+    /// failed, otherwise with whatever the first chunk failed with.
+    pub fn get_player_summaries(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        match self.get_player_summaries_from(steam_ids, 0) {
+            Err(SteamFailure::SummaryChunk { chunk: 0, source, .. }) => Err(*source),
+            res => res,
+        }
+    }
+
+    /// Same as [`Self::get_player_summaries`], but skips the first `start_chunk` chunks of 100 IDs.
+    /// When a chunk fails, the [`SteamFailure::SummaryChunk`] error carries its index, so a caller can
+    /// resume from exactly that chunk.
+    pub fn get_player_summaries_from(&self, steam_ids: &[SteamId], start_chunk: usize) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        if steam_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut ret = Vec::with_capacity(steam_ids.len());
+        for (i, chunk) in steam_ids.chunks(MAX_IDS_PER_REQUEST).enumerate().skip(start_chunk) {
+            tracing::debug!(chunk = i, ids = chunk.len(), "Fetching player summaries");
+            match self.get_player_summaries_chunk(chunk) {
+                Ok(mut players) => ret.append(&mut players),
+                Err(e) => return Err(SteamFailure::SummaryChunk {
+                    chunk: i,
+                    fetched: ret,
+                    source: Box::new(e),
+                }),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Same as [`Self::get_player_summaries`], but with every chunk of 100 IDs requested at once on rayon's thread
+    /// pool. Results come back sorted by `steam_id`, and a failure is reported for the first chunk that failed, with
+    /// the chunks before it (also sorted) as the [`SteamFailure::SummaryChunk`]'s `fetched`.
+    #[cfg(feature = "parallel")]
+    pub fn get_player_summaries_parallel(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        use rayon::prelude::*;
+
+        let chunks = steam_ids.par_chunks(MAX_IDS_PER_REQUEST)
+            .map(|chunk| self.get_player_summaries_chunk(chunk))
+            .collect::<Vec<_>>();
+
+        let mut ret = Vec::with_capacity(steam_ids.len());
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            match chunk {
+                Ok(mut players) => ret.append(&mut players),
+                Err(e) => {
+                    ret.sort_unstable_by_key(|s| s.steam_id);
+                    return Err(SteamFailure::SummaryChunk {
+                        chunk: i,
+                        fetched: ret,
+                        source: Box::new(e),
+                    });
+                },
+            }
+        }
+
+        ret.sort_unstable_by_key(|s| s.steam_id);
+        Ok(ret)
+    }
+
+    /// Ban status for each of `steam_ids`, fetched 100 at a time.
+    pub fn get_player_bans(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerBan>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            players: Vec<PlayerBan>,
+        }
+
+        let mut bans = Vec::with_capacity(steam_ids.len());
+        for chunk in steam_ids.chunks(MAX_IDS_PER_REQUEST) {
+            let url = self.api_url(
+                "ISteamUser/GetPlayerBans/v1",
+                &[("key", self.api_key), ("steamids", chunk.iter().join(",").as_str())],
+            );
+            let mut res: Response = self.get_json(url)?;
+            bans.append(&mut res.players);
+        }
+
+        Ok(bans)
+    }
+
+    /// Downloads an image, e.g. one of the avatar URLs in a [`PlayerSummary`].
+    pub fn download_avatar(&self, url: &str) -> Result<Vec<u8>, SteamFailure> {
+        let url = Url::parse(url).map_err(|_| SteamFailure::InvalidAvatarUrl(url.to_string()))?;
+        Ok(self.get(url)?.body)
+    }
+
+    /// Like [`Self::get_player_summaries`], but a failed chunk doesn't stop the rest. Returns every summary that was
+    /// fetched, along with each failed chunk's IDs and error so just those can be retried.
+    pub fn get_player_summaries_partial(&self, steam_ids: &[SteamId]) -> (Vec<PlayerSummary>, Vec<(Vec<SteamId>, SteamFailure)>) {
+        let mut summaries = Vec::with_capacity(steam_ids.len());
+        let mut failures = Vec::new();
+        for chunk in steam_ids.chunks(MAX_IDS_PER_REQUEST) {
+            match self.get_player_summaries_chunk(chunk) {
+                Ok(mut players) => summaries.append(&mut players),
+                Err(e) => failures.push((chunk.to_vec(), e)),
+            }
+        }
+
+        (summaries, failures)
+    }
+
+    /// Summary of the client's own account, as configured with [`SteamClientBuilder::root_id`].
+    pub fn my_summary(&self) -> Result<PlayerSummary, SteamFailure> {
+        let root_id = self.root_id.ok_or(SteamFailure::NoRootId)?;
+        self.get_player_summaries_chunk(&[root_id])?
+            .into_iter()
+            .next()
+            .ok_or(SteamFailure::NoSummary(root_id))
+    }
+
+    /// Fetches summaries for IDs already joined the way Steam expects them, e.g. `"1,2,3"`.
+    /// Steam only takes 100 IDs per request, so longer lists are rejected before sending anything.
+    pub fn get_player_summaries_raw(&self, steam_ids: &str) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        let count = steam_ids.split(',').filter(|id| !id.trim().is_empty()).count();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        if count > MAX_IDS_PER_REQUEST {
+            return Err(SteamFailure::TooManyIds(count));
+        }
+
+        self.fetch_player_summaries(steam_ids)
+    }
+
+    /// A single summaries response as plain JSON, to see what Steam actually sent when it won't deserialize.
+    /// Like [`Self::get_player_summaries_raw`], more than 100 IDs are rejected.
+    pub fn get_player_summaries_json(&self, steam_ids: &[SteamId]) -> Result<serde_json::Value, SteamFailure> {
+        if steam_ids.len() > MAX_IDS_PER_REQUEST {
+            return Err(SteamFailure::TooManyIds(steam_ids.len()));
+        }
+
+        self.get_json(self.player_summaries_url(&steam_ids.iter().join(",")))
+    }
+
+    fn get_player_summaries_chunk(&self, chunk: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        self.fetch_player_summaries(&chunk.iter().join(","))
+    }
+
+    fn fetch_player_summaries(&self, steam_ids: &str) -> Result<Vec<PlayerSummary>, SteamFailure> {
+        #[derive(Debug, Deserialize)]
+        struct Players {
+            // Kept as plain JSON at first so each summary's `raw_json` can be filled in. Left out entirely when none
+            // of the IDs were valid
+            #[serde(default)]
+            players: Vec<serde_json::Value>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Response {
+            response: Players,
+        }
+
+        let res: Response = self.get_json(self.player_summaries_url(steam_ids))?;
+
+        res.response.players.into_iter()
+            .map(|player| -> Result<PlayerSummary, SteamFailure> {
+                // Deserializing from a reference so `SteamId` can borrow its string
+                let mut summary = PlayerSummary::deserialize(&player)?;
+                summary.raw_json = Some(player.to_string());
+                Ok(summary)
+            })
+            .collect()
+    }
+
+    fn player_summaries_url(&self, steam_ids: &str) -> Url {
+        let mut params = vec![("key", self.api_key), ("steamids", steam_ids)];
+        if let Some(language) = &self.language {
+            params.push(("language", language.as_str()));
+        }
+        self.api_url(
+            "ISteamUser/GetPlayerSummaries/v0002",
+            &params,
+        )
+    }
+}
+
+/// `body` with invalid UTF-8 and unpaired `\uD800`-`\uDFFF` escapes replaced by U+FFFD, or `None` if it had neither.
+fn replace_invalid_text(body: &[u8]) -> Option<Vec<u8>> {
+    // The code unit of a `\uXXXX` escape at the start of `s`
+    fn code_unit(s: &str) -> Option<u16> {
+        s.strip_prefix("\\u")
+            .and_then(|hex| hex.get(..4))
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+    }
+
+    let text = String::from_utf8_lossy(body);
+    let mut changed = matches!(text, Cow::Owned(_));
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_ref();
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let len = match code_unit(rest) {
+            Some(0xD800..=0xDBFF) if code_unit(&rest[6..]).is_some_and(|low| (0xDC00..=0xDFFF).contains(&low)) => 12,
+            Some(0xD800..=0xDFFF) => {
+                out.push_str("\\uFFFD");
+                rest = &rest[6..];
+                changed = true;
+                continue;
+            },
+            // Copied as is, including `\\` so its second backslash isn't mistaken for the start of an escape
+            _ => 1 + rest[1..].chars().next().map_or(0, char::len_utf8),
+        };
+        out.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+
+    changed.then(|| out.into_bytes())
+}
+
+/// `base_url` with a trailing slash, since joining an endpoint onto it would replace the last path segment otherwise.
+pub(crate) fn checked_base_url(mut base_url: Url) -> Result<Url, SteamFailure> {
+    if base_url.cannot_be_a_base() {
+        return Err(SteamFailure::InvalidBaseUrl(base_url.to_string()));
+    }
+    if !base_url.path().ends_with('/') {
+        let path = format!("{}/", base_url.path());
+        base_url.set_path(&path);
+    }
+    Ok(base_url)
+}
+
+/// The text of every `<tag>...</tag>` in `xml`, minus any CDATA wrapper. Steam's community XML is flat and
+/// predictable enough that this beats pulling in a full XML parser.
+fn xml_values<'x>(xml: &'x str, tag: &str) -> Vec<&'x str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        let value = rest[..end].trim();
+        values.push(value.strip_prefix("<![CDATA[").and_then(|v| v.strip_suffix("]]>")).unwrap_or(value));
+        rest = &rest[end + close.len()..];
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use httpmock::prelude::*;
+    use crate::transport::mock::{self, MockTransport};
+    use super::*;
+
+    #[test]
+    fn test_socks5_proxy_rejects_other_schemes() {
+        let res = SteamClient::builder("key")
+            .socks5_proxy(Url::parse("http://127.0.0.1:8080").unwrap())
+            .build();
+
+        assert!(matches!(res, Err(SteamFailure::InvalidProxy(url)) if url.scheme() == "http"));
+    }
+
+    #[test]
+    fn test_socks5_proxy_is_used_for_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 1];
+            stream.read_exact(&mut greeting).unwrap();
+            tx.send(greeting[0]).unwrap();
+            // Dropping the stream here makes the client's request fail, which is all we need
+        });
+
+        let client = SteamClient::builder("key")
+            .socks5_proxy(Url::parse(&format!("socks5://127.0.0.1:{port}")).unwrap())
+            .build()
+            .unwrap();
+        assert!(client.get_friend_list("1").is_err());
+
+        // Every SOCKS5 handshake starts with the protocol version
+        assert_eq!(0x05, rx.recv().unwrap());
+    }
+
+    #[test]
+    fn test_base_url_against_local_server() {
+        let server = MockServer::start();
+        let friends = server.mock(|when, then| {
+            when.method(GET).path("/steam/ISteamUser/GetFriendList/v0001").query_param("steamid", "1");
+            then.status(200).body(mock::friend_list_body(&[2, 3]));
+        });
+        let private = server.mock(|when, then| {
+            when.method(GET).path("/steam/ISteamUser/GetFriendList/v0001").query_param("steamid", "4");
+            then.status(403);
+        });
+        let summaries = server.mock(|when, then| {
+            when.method(GET).path("/steam/ISteamUser/GetPlayerSummaries/v0002").query_param("steamids", "2,3");
+            then.status(200).body(mock::summaries_body("2,3"));
+        });
+        let client = SteamClient::new("key").with_base_url(&server.url("/steam")).unwrap();
+
+        let steam_ids = client.get_friend_list("1").unwrap().iter().map(|f| f.steam_id).collect::<Vec<_>>();
+        assert_eq!(vec![SteamId(2), SteamId(3)], steam_ids);
+        let names = client.get_player_summaries(&steam_ids).unwrap();
+        assert_eq!(vec!["name_2", "name_3"], names.iter().map(|s| s.persona_name.as_str()).collect::<Vec<_>>());
+        assert!(matches!(client.get_friend_list("4"), Err(SteamFailure::PrivateProfile(SteamId(4)))));
+        friends.assert();
+        private.assert();
+        summaries.assert();
+    }
+
+    #[test]
+    fn test_base_url() {
+        let mock = MockTransport::new(|_| Ok(mock::ok(r#"{"applist":{"apps":[]}}"#)));
+        let client = SteamClient::builder("key")
+            .base_url(Url::parse("http://localhost:8080/api").unwrap())
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+        client.get_app_list().unwrap();
+        assert_eq!("http://localhost:8080/api/ISteamApps/GetAppList/v2/", mock.requests()[0].as_str());
+
+        assert!(matches!(SteamClient::new("key").with_base_url("not a url"), Err(SteamFailure::InvalidBaseUrl(_))));
+        assert!(matches!(SteamClient::new("key").with_base_url("mailto:steam@example.com"), Err(SteamFailure::InvalidBaseUrl(_))));
+    }
+
+    #[test]
+    fn test_get_player_summaries_resume_from_failed_chunk() {
+        let fail = Arc::new(AtomicBool::new(true));
+        let mock = {
+            let fail = Arc::clone(&fail);
+            MockTransport::new(move |url| {
+                let ids = mock::param(url, "steamids");
+                if ids.starts_with("201,") && fail.load(Ordering::SeqCst) {
+                    Ok(mock::ok("not json"))
+                } else {
+                    Ok(mock::ok(&mock::summaries_body(&ids)))
+                }
+            })
+        };
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+        let ids = (1..=250).map(SteamId).collect::<Vec<_>>();
+
+        let (chunk, fetched) = match client.get_player_summaries(&ids) {
+            Err(SteamFailure::SummaryChunk { chunk, fetched, .. }) => (chunk, fetched),
+            other => panic!("Expected a chunk failure, got {other:?}"),
+        };
+        assert_eq!(2, chunk);
+        assert_eq!(200, fetched.len());
+        assert_eq!(3, mock.requests().len());
+
+        fail.store(false, Ordering::SeqCst);
+        let rest = client.get_player_summaries_from(&ids, chunk).unwrap();
+        assert_eq!(50, rest.len());
+        assert_eq!(SteamId(201), rest[0].steam_id);
+
+        // Only the failed chunk was fetched again
+        let requests = mock.requests();
+        assert_eq!(4, requests.len());
+        assert!(mock::param(&requests[3], "steamids").starts_with("201,"));
+
+        // Nothing to resume from when the first chunk fails, so that's just the error
+        fail.store(true, Ordering::SeqCst);
+        assert!(matches!(client.get_player_summaries(&ids[200..]), Err(SteamFailure::Deserialize(_))));
+    }
+
+    #[test]
+    fn test_app_id_round_trips_through_db() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let app_id: AppId = conn.query_row("SELECT ?", [AppId(440)], |row| row.get(0)).unwrap();
+
+        assert_eq!(AppId(440), app_id);
+    }
+
+    #[test]
+    fn test_app_id_query_param_is_plain_integer() {
+        let url = Url::parse_with_params(
+            "https://api.steampowered.com/",
+            &[("appid", AppId::from(440).to_string())],
+        ).unwrap();
+
+        assert_eq!(Some("appid=440"), url.query());
+    }
+
+    #[test]
+    fn test_check_mutual() {
+        let mock = MockTransport::new(|url| {
+            match mock::param(url, "steamid").as_str() {
+                "2" => Ok(mock::ok(&mock::friend_list_body(&[1, 3]))),
+                "3" => Ok(mock::ok(&mock::friend_list_body(&[2]))),
+                _ => Ok(mock::response(StatusCode::UNAUTHORIZED, "")),
+            }
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(Some(true), client.check_mutual(&SteamId(1), &SteamId(2)).unwrap());
+        assert_eq!(Some(false), client.check_mutual(&SteamId(1), &SteamId(3)).unwrap());
+        assert_eq!(None, client.check_mutual(&SteamId(1), &SteamId(4)).unwrap());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_get_player_summaries_parallel() {
+        let mock = MockTransport::new(|url| {
+            let ids = mock::param(url, "steamids");
+            if ids.starts_with("201,") {
+                Ok(mock::ok("not json"))
+            } else {
+                Ok(mock::ok(&mock::summaries_body(&ids)))
+            }
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        let ids = (1..=200).map(SteamId).collect::<Vec<_>>();
+        let summaries = client.get_player_summaries_parallel(&ids).unwrap();
+        assert_eq!(ids, summaries.iter().map(|s| s.steam_id).collect::<Vec<_>>());
+        // Sorted by steam_id, not by the order they were asked for in
+        let shuffled = ids.iter().rev().copied().collect::<Vec<_>>();
+        let summaries = client.get_player_summaries_parallel(&shuffled).unwrap();
+        assert_eq!(ids, summaries.iter().map(|s| s.steam_id).collect::<Vec<_>>());
+
+        let ids = (1..=300).map(SteamId).collect::<Vec<_>>();
+        match client.get_player_summaries_parallel(&ids) {
+            Err(SteamFailure::SummaryChunk { chunk: 2, fetched, .. }) => assert_eq!(200, fetched.len()),
+            res => panic!("Expected chunk 2 to fail, got {res:?}"),
+        }
+        // Two chunks each for the first two calls, and all three are still requested for the last
+        assert_eq!(7, mock.requests().len());
+    }
+
+    #[test]
+    fn test_get_user_groups() {
+        let mock = MockTransport::new(|url| match mock::param(url, "steamid").as_str() {
+            "1" => Ok(mock::ok(r#"{"response":{"success":true,"groups":[{"gid":"4"},{"gid":"103582791429521408"}]}}"#)),
+            "2" => Ok(mock::ok(r#"{"response":{"success":false,"error":"Private profile"}}"#)),
+            _ => Ok(mock::response(StatusCode::FORBIDDEN, "")),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(vec![GroupId(4), GroupId(103582791429521408)], client.get_user_groups("1").unwrap());
+        assert!(matches!(client.get_user_groups("2"), Err(SteamFailure::PrivateProfile(SteamId(2)))));
+        assert!(matches!(client.get_user_groups("3"), Err(SteamFailure::PrivateProfile(SteamId(3)))));
+    }
+
+    #[test]
+    fn test_shared_groups() {
+        let mock = MockTransport::new(|url| {
+            let body = match mock::param(url, "steamid").as_str() {
+                "1" => r#"{"response":{"success":true,"groups":[{"gid":"1"},{"gid":"2"},{"gid":"3"}]}}"#,
+                "2" => r#"{"response":{"success":true,"groups":[{"gid":"4"},{"gid":"3"},{"gid":"2"}]}}"#,
+                _ => r#"{"response":{"success":false,"error":"Private profile"}}"#,
+            };
+            Ok(mock::ok(body))
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(vec![GroupId(2), GroupId(3)], client.shared_groups(&SteamId(1), &SteamId(2)).unwrap());
+        assert_eq!(Vec::<GroupId>::new(), client.shared_groups(&SteamId(1), &SteamId(3)).unwrap());
+    }
+
+    #[test]
+    fn test_my_summary() {
+        let mock = MockTransport::new(|url| Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids")))));
+        let client = SteamClient::builder("key")
+            .root_id(SteamId(76561197996714010))
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let summary = client.my_summary().unwrap();
+
+        assert_eq!(SteamId(76561197996714010), summary.steam_id);
+        assert_eq!("name_76561197996714010", summary.persona_name);
+        assert_eq!("76561197996714010", mock::param(&mock.requests()[0], "steamids"));
+    }
+
+    #[test]
+    fn test_my_summary_without_root_id() {
+        let mock = MockTransport::new(|_| panic!("No request should be made"));
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert!(matches!(client.my_summary(), Err(SteamFailure::NoRootId)));
+    }
+
+    #[test]
+    fn test_get_player_summaries_empty_makes_no_requests() {
+        let mock = MockTransport::new(|_| panic!("No request should be made"));
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        assert!(client.get_player_summaries(&[]).unwrap().is_empty());
+        assert!(client.get_player_summaries_raw("").unwrap().is_empty());
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn test_get_player_summaries_raw_rejects_over_100_ids() {
+        let mock = MockTransport::new(|_| panic!("No request should be made"));
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+        let ids = (1..=101).join(",");
+
+        assert!(matches!(client.get_player_summaries_raw(&ids), Err(SteamFailure::TooManyIds(101))));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn test_account_creation_era() {
+        assert_eq!(AccountEra::Early2003To2005, SteamId(76561197960265728 + 1_000_000).account_creation_era());
+        assert_eq!(AccountEra::Y2006To2010, SteamId(76561197996714010).account_creation_era());
+        assert_eq!(AccountEra::Y2011To2013, SteamId(76561198060265728).account_creation_era());
+        assert_eq!(AccountEra::Y2014To2016, SteamId(76561198300000000).account_creation_era());
+        assert_eq!(AccountEra::Y2017To2019, SteamId(76561198400000000).account_creation_era());
+        assert_eq!(AccountEra::Y2020Onwards, SteamId(76561199000000000).account_creation_era());
+    }
+
+    #[test]
+    fn test_language_is_sent_with_summaries() {
+        let mock = MockTransport::new(|url| Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids")))));
+        let client = SteamClient::builder("key")
+            .language("german")
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let summaries = client.get_player_summaries(&[SteamId(1)]).unwrap();
+
+        assert_eq!("name_1", summaries[0].persona_name);
+        assert_eq!("german", mock::param(&mock.requests()[0], "language"));
+    }
+
+    #[test]
+    fn test_get_group_members_follows_pages() {
+        let mock = MockTransport::new(|url| {
+            if url.query_pairs().any(|(k, v)| k == "p" && v == "2") {
+                Ok(mock::ok(include_str!("../tests/fixtures/group_members_page2.xml")))
+            } else {
+                Ok(mock::ok(include_str!("../tests/fixtures/group_members_page1.xml")))
+            }
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        let members = client.get_group_members("https://steamcommunity.com/groups/example/").unwrap();
+
+        assert_eq!(
+            vec![SteamId(76561197960265729), SteamId(76561197960265730), SteamId(76561197960265731), SteamId(76561197960265732)],
+            members,
+        );
+        let requests = mock.requests();
+        assert_eq!(2, requests.len());
+        assert_eq!("https://steamcommunity.com/groups/example/memberslistxml/?xml=1", requests[0].as_str());
+    }
+
+    #[test]
+    fn test_max_response_bytes_checks_content_length() {
+        let mock = MockTransport::new(|url| {
+            let mut res = mock::ok(&mock::summaries_body(&mock::param(url, "steamids")));
+            res.headers.insert(CONTENT_LENGTH, reqwest::header::HeaderValue::from(100u64 * 1024 * 1024));
+            Ok(res)
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        let res = client.get_player_summaries_raw("1");
+
+        assert!(matches!(res, Err(SteamFailure::ResponseTooLarge { limit }) if limit == 50 * 1024 * 1024), "{res:?}");
+    }
+
+    #[test]
+    fn test_max_response_bytes_checks_body() {
+        let mock = MockTransport::new(|url| Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids")))));
+        let client = SteamClient::builder("key")
+            .max_response_bytes(10)
+            .transport(mock)
+            .build()
+            .unwrap();
+
+        assert!(matches!(client.get_player_summaries_raw("1"), Err(SteamFailure::ResponseTooLarge { limit: 10 })));
+    }
+
+    #[test]
+    fn test_parse_friend_list() {
+        let friends = parse_friend_list(include_bytes!("../tests/fixtures/friend_list.json")).unwrap();
+        assert_eq!(
+            vec![
+                (SteamId(76561197960265729), Some(Utc.with_ymd_and_hms(2010, 1, 1, 0, 0, 0).unwrap())),
+                (SteamId(76561197960287930), Some(Utc.with_ymd_and_hms(2015, 6, 1, 12, 0, 0).unwrap())),
+            ],
+            friends.iter().map(|f| (f.steam_id, f.friend_since)).collect::<Vec<_>>()
+        );
+
+        assert!(parse_friend_list(include_bytes!("../tests/fixtures/friend_list_empty.json")).unwrap().is_empty());
+        assert!(parse_friend_list(include_bytes!("../tests/fixtures/friend_list_missing_friends.json")).unwrap().is_empty());
+        // What comes back with the 401 for a private profile isn't JSON at all, `get_friend_list` goes by the status
+        assert!(matches!(
+            parse_friend_list(include_bytes!("../tests/fixtures/friend_list_private.html")),
+            Err(SteamFailure::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_friend_list_empty() {
+        for body in [
+            include_str!("../tests/fixtures/friend_list_empty.json"),
+            include_str!("../tests/fixtures/friend_list_missing_friends.json"),
+        ] {
+            let client = SteamClient::builder("key")
+                .transport(MockTransport::new(move |_| Ok(mock::ok(body))))
+                .build()
+                .unwrap();
+
+            assert!(client.get_friend_list("1").unwrap().is_empty(), "Expected no friends from {body}");
+        }
+    }
+
+    #[test]
+    fn test_estimate_requests() {
+        assert_eq!(0, SteamClient::estimate_requests(&PlannedOp::Summaries { ids: 0 }));
+        assert_eq!(1, SteamClient::estimate_requests(&PlannedOp::Summaries { ids: 100 }));
+        assert_eq!(3, SteamClient::estimate_requests(&PlannedOp::Summaries { ids: 201 }));
+        assert_eq!(7, SteamClient::estimate_requests(&PlannedOp::FriendLists { users: 7 }));
+        assert_eq!(7, SteamClient::estimate_requests(&PlannedOp::OwnedGames { users: 7 }));
+        assert_eq!(4, SteamClient::estimate_requests(&PlannedOp::Sync { friends: 250 }));
+        assert_eq!(0, SteamClient::estimate_requests(&PlannedOp::FriendNetwork { depth: 0, avg_friends: 50 }));
+        assert_eq!(1, SteamClient::estimate_requests(&PlannedOp::FriendNetwork { depth: 1, avg_friends: 50 }));
+        assert_eq!(1 + 50 + 2500, SteamClient::estimate_requests(&PlannedOp::FriendNetwork { depth: 3, avg_friends: 50 }));
+        assert_eq!(usize::MAX, SteamClient::estimate_requests(&PlannedOp::FriendNetwork { depth: 255, avg_friends: 1000 }));
+    }
+
+    #[test]
+    fn test_group_by_current_game() {
+        let body = r#"{"response":{"players":[
+            {"steamid":"1","personaname":"one","profileurl":"one_url","gameid":"440"},
+            {"steamid":"2","personaname":"two","profileurl":"two_url"},
+            {"steamid":"3","personaname":"three","profileurl":"three_url","gameid":"570"},
+            {"steamid":"4","personaname":"four","profileurl":"four_url","gameid":"440"},
+            {"steamid":"5","personaname":"five","profileurl":"five_url","gameid":"13345977030584049664"}
+        ]}}"#;
+        let client = SteamClient::builder("key")
+            .transport(MockTransport::new(move |_| Ok(mock::ok(body))))
+            .build()
+            .unwrap();
+        let summaries = client.get_player_summaries_raw("1,2,3,4,5").unwrap();
+
+        let games = group_by_current_game(&summaries);
+
+        assert_eq!(2, games.len());
+        assert_eq!(vec![SteamId(1), SteamId(4)], games[&AppId(440)]);
+        assert_eq!(vec![SteamId(3)], games[&AppId(570)]);
+    }
+
+    #[test]
+    fn test_app_list_uses_its_own_timeout() {
+        let mock = MockTransport::new(|url| {
+            if url.path().contains("GetAppList") {
+                Ok(mock::ok(r#"{"applist":{"apps":[{"appid":440,"name":"Team Fortress 2"}]}}"#))
+            } else {
+                Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids"))))
+            }
+        });
+        let client = SteamClient::builder("key")
+            .timeout(Duration::from_secs(5))
+            .timeout_for_app_list(Duration::from_secs(60))
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+
+        let apps = client.get_app_list().unwrap();
+        client.get_player_summaries(&[SteamId(1)]).unwrap();
+
+        assert_eq!(vec![App { app_id: AppId(440), name: "Team Fortress 2".to_string() }], apps);
+        assert_eq!(vec![Some(Duration::from_secs(60)), Some(Duration::from_secs(5))], mock.timeouts());
+    }
+
+    #[test]
+    fn test_app_list_falls_back_to_global_timeout() {
+        let mock = MockTransport::new(|_| Ok(mock::ok(r#"{"applist":{"apps":[]}}"#)));
+        let client = SteamClient::builder("key")
+            .timeout(Duration::from_secs(5))
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+
+        client.get_app_list().unwrap();
+
+        assert_eq!(vec![Some(Duration::from_secs(5))], mock.timeouts());
+    }
+
+    #[test]
+    fn test_default_timeout() {
+        let mock = MockTransport::new(|_| Ok(mock::ok(r#"{"friendslist":{"friends":[]}}"#)));
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+        client.get_friend_list("1").unwrap();
+        let client = client.with_timeout(Duration::from_secs(90));
+        client.get_friend_list("1").unwrap();
+
+        assert_eq!(vec![Some(DEFAULT_TIMEOUT), Some(Duration::from_secs(90))], mock.timeouts());
+        // And with the real `reqwest` client
+        assert_eq!(Some(Duration::from_millis(1)), SteamClient::new("key").with_timeout(Duration::from_millis(1)).timeout);
+    }
+
+    #[test]
+    fn test_with_client() {
+        // Cloning a `reqwest` client shares its connection pool
+        let shared = Client::builder().timeout(Duration::from_secs(5)).build().unwrap();
+        let first = SteamClient::with_client("key", shared.clone());
+        let second = SteamClient::builder("other key").client(shared).timeout(Duration::from_secs(1)).build().unwrap();
+
+        // The injected client's own timeout is left alone unless one is asked for
+        assert_eq!(None, first.timeout);
+        assert_eq!(Some(Duration::from_secs(1)), second.timeout);
+        assert_eq!(Some(DEFAULT_TIMEOUT), SteamClient::new("key").timeout);
+    }
+
+    #[test]
+    fn test_social_distance() {
+        // 1 - 2 - 3 - 4, plus 2 - 5 where 5's list is private but would lead to 6
+        let mock = MockTransport::new(|url| {
+            let friends: &[i64] = match mock::param(url, "steamid").as_str() {
+                "1" => &[2],
+                "2" => &[1, 3, 5],
+                "3" => &[2, 4],
+                "4" => &[3],
+                _ => return Ok(mock::response(StatusCode::UNAUTHORIZED, "")),
+            };
+            Ok(mock::ok(&mock::friend_list_body(friends)))
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(Some(0), client.social_distance(&SteamId(1), &SteamId(1), 0).unwrap());
+        assert_eq!(Some(1), client.social_distance(&SteamId(1), &SteamId(2), 3).unwrap());
+        assert_eq!(Some(3), client.social_distance(&SteamId(1), &SteamId(4), 3).unwrap());
+        assert_eq!(None, client.social_distance(&SteamId(1), &SteamId(4), 2).unwrap());
+        assert_eq!(None, client.social_distance(&SteamId(1), &SteamId(6), 5).unwrap());
+    }
+
+    #[test]
+    fn test_get_friend_graph() {
+        // 1 - 2 - 4 - 5, plus 1 - 3 and 2 - 3 where 3's list is private
+        let mock = MockTransport::new(|url| {
+            let friends: &[i64] = match mock::param(url, "steamid").as_str() {
+                "1" => &[2, 3],
+                "2" => &[1, 3, 4],
+                "4" => &[2, 5],
+                "5" => &[4],
+                _ => return Ok(mock::response(StatusCode::UNAUTHORIZED, "")),
+            };
+            Ok(mock::ok(&mock::friend_list_body(friends)))
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+        let crawled = |graph: &FriendGraph| graph.friends.keys().map(|id| id.0).collect::<Vec<_>>();
+
+        assert!(client.get_friend_graph(&SteamId(1), 0, 100).unwrap().friends.is_empty());
+        let graph = client.get_friend_graph(&SteamId(1), 1, 100).unwrap();
+        assert_eq!(vec![SteamId(2), SteamId(3)], graph.friends[&SteamId(1)]);
+        assert_eq!(vec![1, 2], crawled(&client.get_friend_graph(&SteamId(1), 2, 100).unwrap()));
+        assert_eq!(vec![1, 2, 4], crawled(&client.get_friend_graph(&SteamId(1), 3, 100).unwrap()));
+        // Everyone was seen by the time 5's list comes up, so nothing is requested twice
+        let requests_before = mock.requests().len();
+        assert_eq!(vec![1, 2, 4, 5], crawled(&client.get_friend_graph(&SteamId(1), 10, 100).unwrap()));
+        assert_eq!(5, mock.requests().len() - requests_before);
+
+        // 1, 2, then 3 (private) use up the budget before 4
+        let requests_before = mock.requests().len();
+        assert_eq!(vec![1, 2], crawled(&client.get_friend_graph(&SteamId(1), 10, 3).unwrap()));
+        assert_eq!(3, mock.requests().len() - requests_before);
+    }
+
+    #[test]
+    fn test_error_statuses_are_not_deserialized() {
+        let mock = MockTransport::new(|url| {
+            if url.path().contains("GetFriendList") {
+                Ok(mock::response(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests"))
+            } else {
+                Ok(mock::response(StatusCode::UNAUTHORIZED, &"x".repeat(10_000)))
+            }
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        match client.get_friend_list("1") {
+            Err(SteamFailure::Http { status, body }) => {
+                assert_eq!(StatusCode::TOO_MANY_REQUESTS, status);
+                assert_eq!("Too Many Requests", body);
+            },
+            other => panic!("Expected an HTTP error, got {other:?}"),
+        }
+        match client.get_player_summaries_raw("1") {
+            Err(SteamFailure::Http { status, body }) => {
+                assert_eq!(StatusCode::UNAUTHORIZED, status);
+                assert_eq!(MAX_ERROR_BODY_BYTES, body.len());
+            },
+            other => panic!("Expected an HTTP error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_get_owned_games() {
+        let mock = MockTransport::new(|url| match mock::param(url, "steamid").as_str() {
+            "1" => Ok(mock::ok(r#"{"response":{"game_count":2,"games":[
+                {"appid":440,"name":"Team Fortress 2","playtime_forever":6000,"playtime_2weeks":90},
+                {"appid":570,"name":"Dota 2","playtime_forever":30}
+            ]}}"#)),
+            // Private game details
+            _ => Ok(mock::ok(r#"{"response":{}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        let games = client.get_owned_games("1").unwrap();
+
+        assert_eq!(
+            vec![
+                OwnedGame { app_id: AppId(440), name: "Team Fortress 2".to_string(), playtime_forever: Playtime(6000), playtime_2weeks: Some(Playtime(90)) },
+                OwnedGame { app_id: AppId(570), name: "Dota 2".to_string(), playtime_forever: Playtime(30), playtime_2weeks: None },
+            ],
+            games
+        );
+        assert_eq!("1", mock::param(&mock.requests()[0], "include_appinfo"));
+        assert_eq!("1", mock::param(&mock.requests()[0], "include_played_free_games"));
+        assert!(client.get_owned_games("2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_vanity_url() {
+        let mock = MockTransport::new(|url| match mock::param(url, "vanityurl").as_str() {
+            "gabelogannewell" | "12345" => Ok(mock::ok(r#"{"response":{"steamid":"76561197960287930","success":1}}"#)),
+            _ => Ok(mock::ok(r#"{"response":{"success":42,"message":"No match"}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        assert_eq!(SteamId(76561197960287930), client.resolve_vanity_url("gabelogannewell").unwrap());
+        assert!(matches!(
+            client.resolve_vanity_url("nobody"),
+            Err(SteamFailure::VanityNotFound(vanity)) if vanity == "nobody"
+        ));
+
+        assert_eq!(SteamId(76561197960287930), client.resolve("https://steamcommunity.com/id/gabelogannewell/").unwrap());
+        // An all-digit vanity name is still a vanity name
+        assert_eq!(SteamId(76561197960287930), client.resolve("steamcommunity.com/id/12345").unwrap());
+        assert_eq!(3, mock.requests().len());
+        assert_eq!(SteamId(76561197960265729), client.resolve("https://steamcommunity.com/profiles/76561197960265729").unwrap());
+        assert_eq!(SteamId(76561197960265730), client.resolve("76561197960265730").unwrap());
+        assert_eq!(3, mock.requests().len());
+    }
+
+    #[test]
+    fn test_steam_id_components() {
+        // STEAM_0:1:11101 / [U:1:22203]
+        let steam_id = SteamId(76561197960287931);
+
+        assert_eq!(1, steam_id.universe());
+        assert_eq!(1, steam_id.account_type());
+        assert_eq!(1, steam_id.instance());
+        assert_eq!(22203, steam_id.account_id());
+        assert_eq!("STEAM_0:1:11101", steam_id.to_steam_id2());
+        assert_eq!("[U:1:22203]", steam_id.to_steam_id3());
+        // Groups are type 7 with instance 0
+        assert_eq!("[g:1:4]", SteamId(103582791429521412).to_steam_id3());
+    }
+
+    #[test]
+    fn test_steam_id_u64() {
+        assert_eq!(SteamId(76561197960287931), SteamId::new(76561197960287931));
+        assert_eq!(76561197960287931, SteamId::new(76561197960287931).as_u64());
+        // Round trips even past `i64::MAX`
+        assert_eq!(u64::MAX, SteamId::new(u64::MAX).as_u64());
+    }
+
+    #[test]
+    fn test_steam_id_deserializes_from_string_or_number() {
+        let expected = SteamId(76561197996714010);
+        assert_eq!(expected, serde_json::from_str::<SteamId>(r#""76561197996714010""#).unwrap());
+        assert_eq!(expected, serde_json::from_str::<SteamId>("76561197996714010").unwrap());
+        assert_eq!(expected, SteamId::deserialize(&serde_json::json!(76561197996714010u64)).unwrap());
+        assert!(serde_json::from_str::<SteamId>("-1").is_err());
+        assert!(serde_json::from_str::<SteamId>("1.5").is_err());
+        assert!(serde_json::from_str::<SteamId>("true").is_err());
+    }
+
+    #[test]
+    fn test_steam_id_try_from_validates() {
+        assert_eq!(SteamId(76561197960287931), SteamId::try_from("76561197960287931").unwrap());
+        assert!(matches!(SteamId::try_from("abc"), Err(InvalidSteamId::Parse(_))));
+        assert!(matches!(SteamId::try_from("12345"), Err(InvalidSteamId::Universe(0))));
+        // Universe 1, account type 0
+        assert!(matches!(SteamId::try_from("72057594037940000"), Err(InvalidSteamId::AccountType(0))));
+        // Steam's own data isn't held to the same standard
+        assert_eq!(SteamId(12345), serde_json::from_str::<SteamId>(r#""12345""#).unwrap());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let attempts = Arc::new(AtomicU64::new(0));
+        let mock = {
+            let attempts = Arc::clone(&attempts);
+            MockTransport::new(move |url| {
+                // Summaries fail twice then work, friend lists never do
+                if url.path().contains("GetFriendList") || attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Ok(mock::response(StatusCode::SERVICE_UNAVAILABLE, ""))
+                } else {
+                    Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids"))))
+                }
+            })
+        };
+        let client = SteamClient::builder("key")
+            .retry_policy(RetryPolicy::new(3, Duration::ZERO))
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+
+        assert_eq!(1, client.get_player_summaries(&[SteamId(1)]).unwrap().len());
+        assert_eq!(3, mock.requests().len());
+
+        match client.get_friend_list("1") {
+            Err(SteamFailure::RetriesExhausted { attempts, source }) => {
+                assert_eq!(3, attempts);
+                assert!(matches!(*source, SteamFailure::Http { status, .. } if status == StatusCode::SERVICE_UNAVAILABLE));
+            },
+            other => panic!("Expected retries to run out, got {other:?}"),
+        }
+        assert_eq!(6, mock.requests().len());
+    }
+
+    #[test]
+    fn test_retry_policy_honors_retry_after() {
+        let limited = Arc::new(AtomicBool::new(true));
+        let mock = {
+            let limited = Arc::clone(&limited);
+            MockTransport::new(move |url| {
+                if limited.swap(false, Ordering::SeqCst) {
+                    let mut res = mock::response(StatusCode::TOO_MANY_REQUESTS, "");
+                    res.headers.insert(RETRY_AFTER, "0".parse().unwrap());
+                    Ok(res)
+                } else {
+                    Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids"))))
+                }
+            })
+        };
+        // The base delay would make this test take an hour if `Retry-After` were ignored
+        let client = SteamClient::builder("key")
+            .retry_policy(RetryPolicy::new(2, Duration::from_secs(3600)))
+            .transport(mock.clone())
+            .build()
+            .unwrap();
+
+        assert_eq!(1, client.get_player_summaries(&[SteamId(1)]).unwrap().len());
+        assert_eq!(2, mock.requests().len());
+    }
+
+    #[test]
+    fn test_player_summary_optional_fields() {
+        let full = r#"{"steamid":"1","personaname":"one","profileurl":"one_url","avatarfull":"one.jpg","personastate":1,"lastlogoff":1700000000,"gameid":"440","gameextrainfo":"Team Fortress 2"}"#;
+        let minimal = r#"{"steamid":"2","personaname":"two","profileurl":"two_url"}"#;
+
+        let full = serde_json::from_str::<PlayerSummary>(full).unwrap();
+        let minimal = serde_json::from_str::<PlayerSummary>(minimal).unwrap();
+
+        assert_eq!("one.jpg", full.avatar_full);
+        assert_eq!(PersonaState::Online, full.persona_state);
+        assert_eq!(Some("Team Fortress 2"), full.game_extra_info.as_deref());
+        assert_eq!(Some(Utc.timestamp_opt(1700000000, 0).unwrap()), full.last_logoff);
+        assert_eq!("", minimal.avatar_full);
+        assert_eq!(PersonaState::Offline, minimal.persona_state);
+        assert_eq!(None, minimal.game_extra_info);
+        assert_eq!(None, minimal.last_logoff);
+    }
+
+    #[test]
+    fn test_get_player_bans() {
+        let mock = MockTransport::new(|url| {
+            let players = mock::param(url, "steamids").split(',')
+                .map(|id| {
+                    let (vac_banned, vac_bans, economy_ban) = if id == "2" { (true, 2, "probation") } else { (false, 0, "none") };
+                    format!(
+                        r#"{{"SteamId":"{id}","CommunityBanned":false,"VACBanned":{vac_banned},"NumberOfVACBans":{vac_bans},"DaysSinceLastBan":0,"NumberOfGameBans":0,"EconomyBan":"{economy_ban}"}}"#
+                    )
+                })
+                .join(",");
+            Ok(mock::ok(&format!(r#"{{"players":[{players}]}}"#)))
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+        let ids = (1..=150).map(SteamId).collect::<Vec<_>>();
+
+        let bans = client.get_player_bans(&ids).unwrap();
+
+        assert_eq!(150, bans.len());
+        assert_eq!(2, mock.requests().len());
+        let banned = bans.iter().filter(|b| b.vac_banned).collect::<Vec<_>>();
+        assert_eq!(1, banned.len());
+        assert_eq!(SteamId(2), banned[0].steam_id);
+        assert_eq!(2, banned[0].number_of_vac_bans);
+        assert_eq!(EconomyBan::Probation, banned[0].economy_ban);
+    }
+
+    #[test]
+    fn test_api_key_is_redacted() {
+        let client = SteamClient::builder("hunter2").root_id(SteamId(1)).build().unwrap();
+        let debug = format!("{client:?}");
+        assert!(!debug.contains("hunter2"), "{debug}");
+        assert!(debug.contains("REDACTED"), "{debug}");
+
+        // Nothing listens on port 1, so this fails with the URL attached
+        let transport = HttpTransport::new(Client::new(), DEFAULT_MAX_RESPONSE_BYTES);
+        let url = Url::parse("http://127.0.0.1:1/ISteamUser/GetFriendList/v0001?key=hunter2&steamid=1").unwrap();
+        let err = transport.get(&url, None).unwrap_err();
+        match &err {
+            SteamFailure::Request(e) => {
+                assert_eq!("key=REDACTED&steamid=1", e.url().and_then(|url| url.query()).unwrap());
+            },
+            other => panic!("Expected a request error, got {other:?}"),
+        }
+        assert!(!err.to_string().contains("hunter2"), "{err}");
+    }
+
+    #[test]
+    fn test_get_recently_played_games() {
+        let mock = MockTransport::new(|url| match mock::param(url, "steamid").as_str() {
+            "1" => Ok(mock::ok(r#"{"response":{"total_count":1,"games":[
+                {"appid":440,"name":"Team Fortress 2","playtime_2weeks":120,"playtime_forever":6000}
+            ]}}"#)),
+            // Private, or nothing played
+            _ => Ok(mock::ok(r#"{"response":{"total_count":0}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(
+            vec![RecentGame { app_id: AppId(440), name: "Team Fortress 2".to_string(), playtime_2weeks: Playtime(120), playtime_forever: Playtime(6000) }],
+            client.get_recently_played_games("1").unwrap()
+        );
+        assert!(client.get_recently_played_games("2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_badges() {
+        let mock = MockTransport::new(|url| match mock::param(url, "steamid").as_str() {
+            "1" => Ok(mock::ok(r#"{"response":{"badges":[
+                {"badgeid":13,"level":512,"completion_time":1577836800,"xp":862,"scarcity":1620402}
+            ],"player_xp":5320,"player_level":42,"player_xp_needed_to_level_up":180}}"#)),
+            "2" => Ok(mock::ok(r#"{"response":{}}"#)),
+            _ => Ok(mock::response(StatusCode::FORBIDDEN, "")),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(
+            vec![Badge { badge_id: 13, level: 512, completion_time: Utc.timestamp_opt(1577836800, 0).unwrap(), xp: 862, scarcity: 1620402 }],
+            client.get_badges("1").unwrap()
+        );
+        assert!(client.get_badges("2").unwrap().is_empty());
+        assert!(client.get_badges("3").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_steam_level() {
+        let mock = MockTransport::new(|url| match mock::param(url, "steamid").as_str() {
+            "1" => Ok(mock::ok(r#"{"response":{"player_level":42}}"#)),
+            _ => Ok(mock::ok(r#"{"response":{}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert_eq!(Some(42), client.get_steam_level("1").unwrap());
+        assert_eq!(None, client.get_steam_level("2").unwrap());
+    }
+
+    #[test]
+    fn test_get_player_achievements() {
+        let mock = MockTransport::new(|url| match mock::param(url, "appid").as_str() {
+            "440" => Ok(mock::ok(r#"{"playerstats":{"steamID":"1","gameName":"Team Fortress 2","achievements":[
+                {"apiname":"TF_PLAY_GAME_EVERYCLASS","achieved":1,"unlocktime":1262304000},
+                {"apiname":"TF_GET_HEALPOINTS","achieved":0,"unlocktime":0}
+            ],"success":true}}"#)),
+            "220" => Ok(mock::response(StatusCode::BAD_REQUEST, r#"{"playerstats":{"error":"Requested app has no stats","success":false}}"#)),
+            "570" => Ok(mock::response(StatusCode::FORBIDDEN, r#"{"playerstats":{"error":"Profile is not public","success":false}}"#)),
+            _ => Ok(mock::response(StatusCode::FORBIDDEN, "Forbidden")),
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        assert_eq!(
+            vec![
+                Achievement {
+                    api_name: "TF_PLAY_GAME_EVERYCLASS".to_string(),
+                    achieved: true,
+                    unlock_time: Some(Utc.with_ymd_and_hms(2010, 1, 1, 0, 0, 0).unwrap()),
+                },
+                Achievement { api_name: "TF_GET_HEALPOINTS".to_string(), achieved: false, unlock_time: None },
+            ],
+            client.get_player_achievements("1", AppId(440)).unwrap()
+        );
+        assert_eq!("1", mock::param(&mock.requests()[0], "steamid"));
+        assert!(client.get_player_achievements("1", AppId(220)).unwrap().is_empty());
+        assert!(client.get_player_achievements("1", AppId(570)).unwrap().is_empty());
+        // A bad key gets a plain 403 without any player stats
+        assert!(matches!(client.get_player_achievements("1", AppId(1)), Err(SteamFailure::Http { .. })));
+    }
+
+    #[test]
+    fn test_download_avatar() {
+        let mock = MockTransport::new(|_| Ok(RawResponse {
+            status: StatusCode::OK,
+            headers: Default::default(),
+            body: vec![0xFF, 0xD8, 0xFF],
+        }));
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+        let summary = serde_json::from_str::<PlayerSummary>(
+            r#"{"steamid":"1","personaname":"one","profileurl":"one_url","avatar":"https://avatars.example.com/1.jpg","avatarmedium":"https://avatars.example.com/1_medium.jpg","avatarfull":"https://avatars.example.com/1_full.jpg"}"#
+        ).unwrap();
+
+        assert_eq!(vec![0xFF, 0xD8, 0xFF], client.download_avatar(&summary.avatar_full).unwrap());
+        assert_eq!("https://avatars.example.com/1_full.jpg", mock.requests()[0].as_str());
+        assert_eq!("https://avatars.example.com/1_medium.jpg", summary.avatar_medium);
+        assert_eq!("https://avatars.example.com/1.jpg", summary.avatar);
+        assert!(matches!(client.download_avatar("not a url"), Err(SteamFailure::InvalidAvatarUrl(_))));
+    }
+
+    #[test]
+    fn test_with_cache() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("steam_cache");
+        let mock = MockTransport::new(|_| Ok(mock::ok(&mock::friend_list_body(&[2]))));
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap()
+            .with_cache(&dir, Duration::from_secs(60));
+        let other_key = SteamClient::builder("other key").transport(mock.clone()).build().unwrap()
+            .with_cache(&dir, Duration::from_secs(60));
+        let expired = SteamClient::builder("key").transport(mock.clone()).build().unwrap()
+            .with_cache(&dir, Duration::ZERO);
+
+        assert_eq!(SteamId(2), client.get_friend_list("1").unwrap()[0].steam_id);
+        assert_eq!(SteamId(2), client.get_friend_list("1").unwrap()[0].steam_id);
+        other_key.get_friend_list("1").unwrap();
+        assert_eq!(1, mock.requests().len());
+        // A different request, or one past the TTL, goes out
+        client.get_friend_list("3").unwrap();
+        expired.get_friend_list("1").unwrap();
+        assert_eq!(3, mock.requests().len());
+
+        assert_eq!(2, std::fs::read_dir(&dir).unwrap().count());
+    }
+
+    #[test]
+    fn test_friend_and_summary_round_trip() {
+        let friend = Friend {
+            steam_id: SteamId(76561197960287930),
+            relationship: Relationship::Friend,
+            friend_since: Some(Utc.timestamp_opt(1262304000, 0).unwrap()),
+        };
+        let json = serde_json::to_string(&friend).unwrap();
+        assert_eq!(r#"{"steamid":"76561197960287930","relationship":"friend","friend_since":1262304000}"#, json);
+        assert_eq!(friend, serde_json::from_str(&json).unwrap());
+        let invite = Friend { friend_since: None, ..friend };
+        assert_eq!(invite, serde_json::from_str(&serde_json::to_string(&invite).unwrap()).unwrap());
+
+        let summary = PlayerSummary {
+            steam_id: SteamId(76561197960287930),
+            persona_name: "Rabscuttle".to_string(),
+            profile_url: "https://steamcommunity.com/id/gabelogannewell/".to_string(),
+            avatar: String::new(),
+            avatar_medium: String::new(),
+            avatar_full: String::new(),
+            persona_state: PersonaState::Online,
+            last_logoff: Some(Utc.timestamp_opt(1700000000, 0).unwrap()),
+            game_id: Some(AppId(440)),
+            game_extra_info: Some("Team Fortress 2".to_string()),
+            raw_json: None,
+        };
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!("76561197960287930", json["steamid"]);
+        assert_eq!("440", json["gameid"]);
+        assert_eq!(summary, PlayerSummary::deserialize(&json).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_text_in_names() {
+        let mock = MockTransport::new(|_| {
+            let mut body = br#"{"response":{"players":[
+                {"steamid":"1","personaname":"half \ud83c an emoji","profileurl":""},
+                {"steamid":"2","personaname":"whole \ud83c\udfae emoji \\ud800","profileurl":""},
+                {"steamid":"3","personaname":"bad "#.to_vec();
+            body.extend_from_slice(b"\xff byte\",\"profileurl\":\"\"}]}}");
+            Ok(RawResponse { body, ..mock::ok("") })
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        let names = client.get_player_summaries_raw("1,2,3").unwrap().into_iter().map(|s| s.persona_name).collect::<Vec<_>>();
+
+        assert_eq!(vec!["half \u{FFFD} an emoji", "whole 🎮 emoji \\ud800", "bad \u{FFFD} byte"], names);
+        assert_eq!(None, replace_invalid_text(br#"{"name":"fine \ud83c\udfae"}"#));
+    }
+
+    #[test]
+    fn test_error_envelopes() {
+        let mock = MockTransport::new(|url| match url.path() {
+            path if path.contains("GetOwnedGames") => Ok(mock::ok(r#"{"error":"Invalid steamid"}"#)),
+            path if path.contains("GetUserGroupList") => Ok(mock::ok(r#"{"response":{"message":"Profile is private"}}"#)),
+            path if path.contains("GetPlayerBans") => Ok(mock::ok(r#"{"response":{"error":"Too many requests"}}"#)),
+            path if path.contains("GetRecentlyPlayedGames") => Ok(mock::ok(r#"{"unexpected":true}"#)),
+            // What Steam sends when none of the IDs were valid
+            _ => Ok(mock::ok(r#"{"response":{}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert!(matches!(client.get_owned_games("1"), Err(SteamFailure::Api(message)) if message == "Invalid steamid"));
+        assert!(matches!(client.get_user_groups("1"), Err(SteamFailure::PrivateProfile(SteamId(1)))));
+        assert!(matches!(client.get_player_bans(&[SteamId(1)]), Err(SteamFailure::Api(message)) if message == "Too many requests"));
+        assert!(matches!(client.get_recently_played_games("1"), Err(SteamFailure::Deserialize(_))));
+        assert!(client.get_player_summaries(&[SteamId(1), SteamId(2)]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_raw_json_responses() {
+        // `steamid` as an object is something no `SteamId` will deserialize from
+        let mock = MockTransport::new(|url| match url.path() {
+            path if path.contains("GetFriendList") => match mock::param(url, "steamid").as_str() {
+                "1" => Ok(mock::ok(r#"{"friendslist":{"friends":[{"steamid":{"id":2},"relationship":"friend","friend_since":0}]}}"#)),
+                _ => Ok(mock::response(StatusCode::UNAUTHORIZED, "")),
+            },
+            _ => Ok(mock::ok(r#"{"response":{"players":[{"steamid":{"id":2},"personaname":"two","profileurl":""}]}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert!(matches!(client.get_friend_list("1"), Err(SteamFailure::Deserialize(_))));
+        assert_eq!(2, client.get_friend_list_json("1").unwrap()["friendslist"]["friends"][0]["steamid"]["id"]);
+        assert!(matches!(client.get_friend_list_json("2"), Err(SteamFailure::PrivateProfile(_))));
+
+        assert!(matches!(client.get_player_summaries_raw("2"), Err(SteamFailure::Deserialize(_))));
+        assert_eq!("two", client.get_player_summaries_json(&[SteamId(2)]).unwrap()["response"]["players"][0]["personaname"]);
+        let ids = (0..101).map(SteamId).collect::<Vec<_>>();
+        assert!(matches!(client.get_player_summaries_json(&ids), Err(SteamFailure::TooManyIds(101))));
+    }
+
+    #[test]
+    fn test_get_friend_list_with_relationship() {
+        let mock = MockTransport::new(|url| match mock::param(url, "relationship").as_str() {
+            "all" => Ok(mock::ok(r#"{"friendslist":{"friends":[
+                {"steamid":"76561197960265729","relationship":"friend","friend_since":1262304000},
+                {"steamid":"76561197960265730","relationship":"all","friend_since":0}
+            ]}}"#)),
+            _ => Ok(mock::ok(r#"{"friendslist":{"friends":[
+                {"steamid":"76561197960265729","relationship":"friend","friend_since":1262304000}
+            ]}}"#)),
+        });
+        let client = SteamClient::builder("key").transport(mock.clone()).build().unwrap();
+
+        assert_eq!(1, client.get_friend_list("1").unwrap().len());
+        assert_eq!("friend", mock::param(&mock.requests()[0], "relationship"));
+        let everyone = client.get_friend_list_with_relationship("1", Relationship::All).unwrap();
+        assert_eq!(vec![Relationship::Friend, Relationship::All], everyone.iter().map(|f| f.relationship).collect::<Vec<_>>());
+        // Pending invites haven't been accepted yet, so there's no `friend_since`
+        assert_eq!(vec![Some(Utc.timestamp_opt(1262304000, 0).unwrap()), None], everyone.iter().map(|f| f.friend_since).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_get_friend_list_private() {
+        let mock = MockTransport::new(|url| match mock::param(url, "steamid").as_str() {
+            "1" => Ok(mock::response(StatusCode::UNAUTHORIZED, include_str!("../tests/fixtures/friend_list_private.html"))),
+            "2" => Ok(mock::response(StatusCode::FORBIDDEN, "<html>Forbidden</html>")),
+            _ => Ok(mock::ok(include_str!("../tests/fixtures/friend_list_empty.json"))),
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+
+        assert!(matches!(client.get_friend_list("1"), Err(SteamFailure::PrivateProfile(SteamId(1)))));
+        assert!(matches!(client.get_friend_list("2"), Err(SteamFailure::PrivateProfile(SteamId(2)))));
+        // Public but empty isn't an error
+        assert!(client.get_friend_list("3").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_player_summaries_partial() {
+        let mock = MockTransport::new(|url| {
+            let ids = mock::param(url, "steamids");
+            if ids.starts_with("101,") {
+                Ok(mock::response(StatusCode::GATEWAY_TIMEOUT, ""))
+            } else {
+                Ok(mock::ok(&mock::summaries_body(&ids)))
+            }
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+        let ids = (1..=350).map(SteamId).collect::<Vec<_>>();
+
+        let (summaries, failures) = client.get_player_summaries_partial(&ids);
+
+        assert_eq!(250, summaries.len());
+        assert_eq!(1, failures.len());
+        assert_eq!((101..=200).map(SteamId).collect::<Vec<_>>(), failures[0].0);
+        assert!(matches!(failures[0].1, SteamFailure::Http { status, .. } if status == StatusCode::GATEWAY_TIMEOUT));
+        assert_eq!(SteamId(201), summaries[100].steam_id);
+    }
+}
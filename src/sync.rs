@@ -0,0 +1,242 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::sql::DbConnection;
+use crate::steam_api::{SteamApi, SteamClient, SteamFailure};
+use crate::transport::ReplayTransport;
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error(transparent)]
+    Steam(#[from] SteamFailure),
+    #[error("Database error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("Couldn't read fixtures: {0}")]
+    Fixtures(#[from] std::io::Error),
+    #[error("Sync timed out after saving {saved} of {total} friends")]
+    TimedOut { saved: usize, total: usize },
+}
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// Every friend's summary was saved and anyone missing from the friend list was marked removed.
+    Completed { friends: usize },
+    /// The deadline passed part-way through. The `saved` summaries are kept, but nobody was marked removed
+    /// since we never got through the whole friend list.
+    TimedOut { saved: usize, total: usize },
+}
+
+
+/// Syncs `steam_id`'s friend list into `db` one chunk of 100 summaries at a time, so only the friend list and a
+/// single chunk are ever in memory. Returns how many friends were synced.
+pub fn sync(client: &dyn SteamApi, db: &mut DbConnection, steam_id: &str) -> Result<usize, SyncError> {
+    match sync_with_deadline(client, db, steam_id, Duration::MAX)? {
+        SyncOutcome::Completed { friends } => Ok(friends),
+        // Can't happen without a deadline, but an error is better than a panic if it ever does
+        SyncOutcome::TimedOut { saved, total } => Err(SyncError::TimedOut { saved, total }),
+    }
+}
+
+
+/// Syncs `steam_id`'s friend list into `db`, giving up once `deadline` has passed.
+/// Summaries are fetched and saved 100 at a time, so a timeout keeps everything saved up to that point.
+/// The deadline is checked between requests, so one slow request can still overrun it by that request's timeout.
+/// `AsyncSteamClient::sync_with_deadline` (with the `async` feature) cuts off a slow request at the deadline too.
+pub fn sync_with_deadline(client: &dyn SteamApi, db: &mut DbConnection, steam_id: &str, deadline: Duration) -> Result<SyncOutcome, SyncError> {
+    let started = Instant::now();
+    let mut friends = client.get_friend_list(steam_id)?;
+    friends.sort_unstable_by_key(|f| f.steam_id);
+    let friend_ids = friends.iter().map(|f| f.steam_id).collect::<Vec<_>>();
+    let total = friends.len();
+
+    db.start_sync()?;
+    let mut saved = 0;
+    for friends_chunk in friends.chunks_mut(100) {
+        if started.elapsed() >= deadline {
+            return Ok(SyncOutcome::TimedOut { saved, total });
+        }
+
+        let chunk_ids = friends_chunk.iter().map(|f| f.steam_id).collect::<Vec<_>>();
+        let mut summaries = client.get_player_summaries(&chunk_ids)?;
+        db.upsert_player_summaries(friends_chunk, &mut summaries)?;
+        saved += summaries.len();
+    }
+
+    db.mark_removed(&friend_ids)?;
+    db.record_friend_count()?;
+    Ok(SyncOutcome::Completed { friends: total })
+}
+
+
+/// Runs a full sync against the responses recorded in `fixtures` (see [`ReplayTransport`]) instead of Steam.
+/// Handy for seeding a demo DB, or an offline end-to-end test. Returns how many friends were synced.
+pub fn replay_sync(fixtures: &Path, db: &mut DbConnection) -> Result<usize, SyncError> {
+    let client = SteamClient::builder("replay")
+        .transport(ReplayTransport::new(fixtures)?)
+        .build()?;
+
+    // The recorded friend list is the same whoever we ask for
+    sync(&client, db, "0")
+}
+
+
+/// Tracks a watchlist instead of a friend list: each of `inputs` (a Steam ID or vanity URL name) is resolved, and
+/// their summaries are saved to `tracked_users`. Returns how many summaries were saved.
+pub fn resolve_and_sync(client: &SteamClient, inputs: &[String], db: &mut DbConnection) -> Result<usize, SyncError> {
+    let steam_ids = inputs.iter()
+        .map(|input| client.resolve(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    let summaries = client.get_player_summaries(&steam_ids)?;
+    db.upsert_tracked_users(&summaries)?;
+
+    Ok(summaries.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use chrono::Utc;
+    use serde::Deserialize;
+    use crate::steam_api::{Friend, PlayerSummary, Relationship, SteamId};
+    use crate::transport::mock::{self, MockTransport};
+    use super::*;
+
+    const ROOT: &str = "76561197960265728";
+
+    fn slow_summaries_client(delay: Duration) -> SteamClient<'static> {
+        let mock = MockTransport::new(move |url| {
+            if url.path().contains("GetFriendList") {
+                let ids = (1..=250).collect::<Vec<_>>();
+                Ok(mock::ok(&mock::friend_list_body(&ids)))
+            } else {
+                sleep(delay);
+                Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids"))))
+            }
+        });
+        SteamClient::builder("key").transport(mock).build().unwrap()
+    }
+
+    fn count_rows(db: &DbConnection) -> usize {
+        db.conn().query_row("SELECT COUNT(*) FROM player_summaries", (), |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_sync_with_deadline_times_out_and_keeps_partial_data() {
+        let client = slow_summaries_client(Duration::from_millis(100));
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.set_owner(SteamId::try_from(ROOT).unwrap()).unwrap();
+
+        // Enough time for two of the three summary chunks
+        let outcome = sync_with_deadline(&client, &mut db, ROOT, Duration::from_millis(150)).unwrap();
+
+        assert_eq!(SyncOutcome::TimedOut { saved: 200, total: 250 }, outcome);
+        assert_eq!(200, count_rows(&db));
+        // A partial sync's count would be misleading
+        assert!(db.friend_count_series(SteamId::try_from(ROOT).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_with_deadline_completes() {
+        let client = slow_summaries_client(Duration::ZERO);
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+        db.set_owner(SteamId::try_from(ROOT).unwrap()).unwrap();
+
+        let outcome = sync_with_deadline(&client, &mut db, ROOT, Duration::from_secs(60)).unwrap();
+
+        assert_eq!(SyncOutcome::Completed { friends: 250 }, outcome);
+        assert_eq!(250, count_rows(&db));
+        let counts = db.friend_count_series(SteamId::try_from(ROOT).unwrap()).unwrap();
+        assert_eq!(vec![250], counts.into_iter().map(|(_, count)| count).collect::<Vec<_>>());
+    }
+
+    /// Three friends, and a summary for each, without going through a transport at all.
+    struct FakeApi;
+
+    impl SteamApi for FakeApi {
+        fn get_friend_list(&self, _steam_id: &str) -> Result<Vec<Friend>, SteamFailure> {
+            Ok((1..=3).map(|id| Friend { steam_id: SteamId(id), relationship: Relationship::Friend, friend_since: Some(Utc::now()) }).collect())
+        }
+
+        fn get_player_summaries(&self, steam_ids: &[SteamId]) -> Result<Vec<PlayerSummary>, SteamFailure> {
+            let ids = steam_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+            Ok(serde_json::from_str::<serde_json::Value>(&mock::summaries_body(&ids)).unwrap()["response"]["players"]
+                .as_array().unwrap()
+                .iter()
+                .map(|player| PlayerSummary::deserialize(player).unwrap())
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_sync_with_fake_api() {
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        assert_eq!(3, sync(&FakeApi, &mut db, ROOT).unwrap());
+        assert_eq!(3, count_rows(&db));
+    }
+
+    #[test]
+    fn test_sync_matches_all_at_once_update() {
+        let client = slow_summaries_client(Duration::ZERO);
+        let stored = |db: &DbConnection| db.get_current_friends().unwrap()
+            .into_iter()
+            .map(|f| (f.steam_id, f.persona_name, f.profile_url, f.friend_since))
+            .collect::<Vec<_>>();
+
+        let mut streamed = DbConnection::new(":memory:").unwrap();
+        streamed.create_tables().unwrap();
+        assert_eq!(250, sync(&client, &mut streamed, ROOT).unwrap());
+
+        let mut all_at_once = DbConnection::new(":memory:").unwrap();
+        all_at_once.create_tables().unwrap();
+        let mut friends = client.get_friend_list(ROOT).unwrap();
+        let mut summaries = client.get_player_summaries(&friends.iter().map(|f| f.steam_id).collect::<Vec<_>>()).unwrap();
+        all_at_once.update_player_summaries(&mut friends, &mut summaries).unwrap();
+
+        assert_eq!(250, stored(&streamed).len());
+        assert_eq!(stored(&all_at_once), stored(&streamed));
+    }
+
+    #[test]
+    fn test_resolve_and_sync() {
+        let mock = MockTransport::new(|url| {
+            if url.path().contains("ResolveVanityURL") {
+                match mock::param(url, "vanityurl").as_str() {
+                    "alice" => Ok(mock::ok(r#"{"response":{"steamid":"76561197960265731","success":1}}"#)),
+                    _ => Ok(mock::ok(r#"{"response":{"success":42,"message":"No match"}}"#)),
+                }
+            } else {
+                Ok(mock::ok(&mock::summaries_body(&mock::param(url, "steamids"))))
+            }
+        });
+        let client = SteamClient::builder("key").transport(mock).build().unwrap();
+        let mut db = DbConnection::new(":memory:").unwrap();
+        db.create_tables().unwrap();
+
+        let inputs = ["alice".to_string(), "76561197960287930".to_string()];
+        assert_eq!(2, resolve_and_sync(&client, &inputs, &mut db).unwrap());
+
+        let mut stmt = db.conn().prepare("SELECT steam_id, persona_name FROM tracked_users ORDER BY steam_id").unwrap();
+        let rows = stmt.query_map((), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (76561197960265731, "name_76561197960265731".to_string()),
+                (76561197960287930, "name_76561197960287930".to_string()),
+            ],
+            rows
+        );
+        // Tracked users aren't friends
+        assert_eq!(0, count_rows(&db));
+
+        let err = resolve_and_sync(&client, &["nobody".to_string()], &mut db).unwrap_err();
+        assert!(matches!(err, SyncError::Steam(SteamFailure::VanityNotFound(vanity)) if vanity == "nobody"));
+    }
+}
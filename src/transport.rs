@@ -0,0 +1,241 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use itertools::Itertools;
+use reqwest::{
+    blocking::Client,
+    header::HeaderMap,
+    StatusCode,
+    Url,
+};
+use crate::steam_api::SteamFailure;
+
+
+/// Everything `SteamClient` needs from an HTTP response.
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+
+/// The layer `SteamClient` sends its requests through. The default is [`HttpTransport`], but anything
+/// that can turn a URL into a response works, which is how the tests avoid hitting Steam.
+pub trait Transport: Send + Sync {
+    /// `timeout` is for the whole request. `None` leaves it up to the transport.
+    fn get(&self, url: &Url, timeout: Option<Duration>) -> Result<RawResponse, SteamFailure>;
+}
+
+
+pub struct HttpTransport {
+    client: Client,
+    max_response_bytes: u64,
+}
+
+impl HttpTransport {
+    /// Bodies over `max_response_bytes` fail with [`SteamFailure::ResponseTooLarge`], without reading past the limit.
+    pub fn new(client: Client, max_response_bytes: u64) -> Self {
+        Self { client, max_response_bytes }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn get(&self, url: &Url, timeout: Option<Duration>) -> Result<RawResponse, SteamFailure> {
+        let too_large = SteamFailure::ResponseTooLarge { limit: self.max_response_bytes };
+        let mut req = self.client.get(url.clone());
+        if let Some(timeout) = timeout {
+            req = req.timeout(timeout);
+        }
+        let res = req.send()?;
+        if res.content_length().is_some_and(|len| len > self.max_response_bytes) {
+            return Err(too_large);
+        }
+
+        let status = res.status();
+        let headers = res.headers().clone();
+        // Content-Length can be missing or wrong, so cap what we actually read too
+        let mut body = Vec::new();
+        res.take(self.max_response_bytes + 1)
+            .read_to_end(&mut body)
+            .map_err(SteamFailure::Body)?;
+        if body.len() as u64 > self.max_response_bytes {
+            return Err(too_large);
+        }
+
+        Ok(RawResponse { status, headers, body })
+    }
+}
+
+
+/// Serves recorded responses from a directory, in file name order, without touching the network.
+/// Files are named `<n>_<method>.json`, e.g. `0001_GetFriendList.json` then `0002_GetPlayerSummaries.json`,
+/// and each request has to be for the method its file is named after. Every response is a 200.
+pub struct ReplayTransport {
+    responses: Mutex<std::vec::IntoIter<PathBuf>>,
+}
+
+impl ReplayTransport {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut responses = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .filter_ok(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect::<Result<Vec<_>, _>>()?;
+        responses.sort_unstable();
+
+        Ok(Self { responses: Mutex::new(responses.into_iter()) })
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn get(&self, url: &Url, _timeout: Option<Duration>) -> Result<RawResponse, SteamFailure> {
+        let replay_error = |msg: String| SteamFailure::Body(io::Error::new(io::ErrorKind::InvalidData, msg));
+        // e.g. `GetFriendList` out of `/ISteamUser/GetFriendList/v0001`
+        let method = url.path_segments().and_then(|mut segments| segments.nth(1)).unwrap_or_default();
+
+        let path = self.responses.lock().unwrap().next()
+            .ok_or_else(|| replay_error(format!("No recorded response left for {method}")))?;
+        let recorded_method = path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.split_once('_'))
+            .map(|(_, recorded_method)| recorded_method);
+        if recorded_method != Some(method) {
+            return Err(replay_error(format!("Expected a {method} request, but {} is next", path.display())));
+        }
+
+        Ok(RawResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: std::fs::read(&path).map_err(SteamFailure::Body)?,
+        })
+    }
+}
+
+
+/// Keeps successful response bodies in `dir` and serves them again for the same request until they're older than
+/// `ttl`, without calling `inner`. Meant for development, to save on the rate limit.
+pub struct CachingTransport {
+    inner: Box<dyn Transport>,
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl CachingTransport {
+    pub fn new(inner: Box<dyn Transport>, dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Self { inner, dir: dir.into(), ttl }
+    }
+
+    /// Where `url`'s response goes. The API key is left out so every key shares the cache, and so it doesn't end up
+    /// on disk. `DefaultHasher` isn't stable across Rust releases, which at worst empties the cache after an upgrade.
+    fn path(&self, url: &Url) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        url.path().hash(&mut hasher);
+        for (name, value) in url.query_pairs().filter(|(name, _)| name != "key") {
+            (name, value).hash(&mut hasher);
+        }
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+}
+
+impl Transport for CachingTransport {
+    fn get(&self, url: &Url, timeout: Option<Duration>) -> Result<RawResponse, SteamFailure> {
+        let path = self.path(url);
+        let age = std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .map(|modified| SystemTime::now().duration_since(modified).unwrap_or_default());
+        if age.is_ok_and(|age| age < self.ttl) {
+            if let Ok(body) = std::fs::read(&path) {
+                return Ok(RawResponse { status: StatusCode::OK, headers: HeaderMap::new(), body });
+            }
+        }
+
+        let res = self.inner.get(url, timeout)?;
+        if res.status.is_success() {
+            // A cache that can't be written to is no reason to fail the request
+            let _ = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, &res.body));
+        }
+        Ok(res)
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::sync::Arc;
+    use super::*;
+
+    type Handler = dyn Fn(&Url) -> Result<RawResponse, SteamFailure> + Send + Sync;
+
+    /// Answers requests with `handler` and remembers every URL (and timeout) it was asked for.
+    #[derive(Clone)]
+    pub(crate) struct MockTransport {
+        handler: Arc<Handler>,
+        requests: Arc<Mutex<Vec<Url>>>,
+        timeouts: Arc<Mutex<Vec<Option<Duration>>>>,
+    }
+
+    impl MockTransport {
+        pub(crate) fn new(handler: impl Fn(&Url) -> Result<RawResponse, SteamFailure> + Send + Sync + 'static) -> Self {
+            Self {
+                handler: Arc::new(handler),
+                requests: Arc::new(Mutex::new(Vec::new())),
+                timeouts: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        pub(crate) fn requests(&self) -> Vec<Url> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// The timeout of each request, in the same order as [`Self::requests`].
+        pub(crate) fn timeouts(&self) -> Vec<Option<Duration>> {
+            self.timeouts.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn get(&self, url: &Url, timeout: Option<Duration>) -> Result<RawResponse, SteamFailure> {
+            self.requests.lock().unwrap().push(url.clone());
+            self.timeouts.lock().unwrap().push(timeout);
+            (self.handler)(url)
+        }
+    }
+
+    pub(crate) fn response(status: StatusCode, body: &str) -> RawResponse {
+        RawResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    pub(crate) fn ok(body: &str) -> RawResponse {
+        response(StatusCode::OK, body)
+    }
+
+    /// Pulls a single query parameter back out of a request URL.
+    pub(crate) fn param(url: &Url, name: &str) -> String {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+            .unwrap_or_else(|| panic!("{url} has no `{name}` param"))
+    }
+
+    /// A `GetFriendList` body listing `steam_ids` as friends.
+    pub(crate) fn friend_list_body(steam_ids: &[i64]) -> String {
+        let friends = steam_ids.iter()
+            .map(|id| format!(r#"{{"steamid":"{id}","relationship":"friend","friend_since":1500000000}}"#))
+            .join(",");
+        format!(r#"{{"friendslist":{{"friends":[{friends}]}}}}"#)
+    }
+
+    /// A `GetPlayerSummaries` body for a comma-separated `steamids` param, naming everyone `name_<id>`.
+    pub(crate) fn summaries_body(steam_ids: &str) -> String {
+        let players = steam_ids.split(',')
+            .map(|id| format!(r#"{{"steamid":"{id}","personaname":"name_{id}","profileurl":"url_{id}"}}"#))
+            .join(",");
+        format!(r#"{{"response":{{"players":[{players}]}}}}"#)
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::Path;
+use chrono::{TimeZone, Utc};
+use steam_web_api_consumer::{
+    sql::DbConnection,
+    steam_api::SteamId,
+    sync::replay_sync,
+};
+
+#[test]
+fn test_replay_sync() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay");
+    // The DB's -wal and -shm files go with the directory
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DbConnection::open_or_recover(dir.path().join("replay_sync.db")).unwrap();
+    db.create_tables().unwrap();
+
+    let synced = replay_sync(&fixtures, &mut db).unwrap();
+    let friends = db.get_current_friends().unwrap();
+
+    assert_eq!(2, synced);
+    let friends = friends.into_iter()
+        .map(|f| (f.steam_id, f.persona_name, f.profile_url, f.friend_since))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (SteamId(76561197960265731), "Alice".to_string(), "https://steamcommunity.com/id/alice/".to_string(), Some(Utc.timestamp_opt(1388534400, 0).unwrap())),
+            (SteamId(76561197960287930), "Bob".to_string(), "https://steamcommunity.com/id/bob/".to_string(), Some(Utc.timestamp_opt(1262304000, 0).unwrap())),
+        ],
+        friends
+    );
+}